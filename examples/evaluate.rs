@@ -0,0 +1,62 @@
+extern crate stick_solo;
+use std::{env, fs::File, io::BufReader};
+use stick_solo::act::switchable_nr::SwitchableNR;
+use stick_solo::plan::cross_entropy_optimizing::experiment::Experiment;
+use stick_solo::plan::cross_entropy_optimizing::reach::ReachReward;
+
+/// Evaluates a saved experiment on the reach task of its world, without any window, and prints
+/// the statistics as json.
+/// Usage: evaluate <experiment> [--episodes M] [--ticks T] [--seed S] [--slack D]
+/// [--replan-every N] [--output path]
+/// Ticks default to those the experiment was trained with.
+fn main() {
+    let args = env::args().collect::<Vec<String>>();
+    if args.len() < 2 {
+        panic!("Bad cmd line parameters.");
+    }
+    let experiment: Experiment =
+        serde_json::from_reader(BufReader::new(File::open(&args[1]).unwrap())).unwrap();
+    let mut num_episodes = 100;
+    let mut num_episode_ticks = match (&experiment.ceo, &experiment.cma_es) {
+        (Some(ceo), _) => ceo.num_episode_ticks,
+        (None, Some(cma_es)) => cma_es.num_episode_ticks,
+        (None, None) => 200,
+    };
+    let mut seed = 0;
+    let mut slack = SwitchableNR::GOAL_REACHED_SLACK;
+    let mut replan_every = None;
+    let mut output = None;
+    let mut options = args[2..].iter();
+    while let Some(option) = options.next() {
+        let value = options.next().expect("Bad cmd line parameters.");
+        match option.as_str() {
+            "--episodes" => num_episodes = value.parse().unwrap(),
+            "--ticks" => num_episode_ticks = value.parse().unwrap(),
+            "--seed" => seed = value.parse().unwrap(),
+            "--slack" => slack = value.parse().unwrap(),
+            "--replan-every" => replan_every = Some(value.parse().unwrap()),
+            "--output" => output = Some(value.clone()),
+            _ => panic!("Bad cmd line parameters."),
+        }
+    }
+
+    let reward = ReachReward {
+        world: experiment.world.clone(),
+        encoder: experiment.encoder.clone(),
+        decoder: experiment.decoder,
+        replan_every,
+    };
+    let evaluation = reward.evaluate(
+        &experiment.policy,
+        num_episodes,
+        num_episode_ticks,
+        seed,
+        slack,
+    );
+    match output {
+        Some(path) => {
+            serde_json::to_writer_pretty(&File::create(path).unwrap(), &evaluation).unwrap()
+        }
+        None => println!("{}", serde_json::to_string_pretty(&evaluation).unwrap()),
+    }
+}
@@ -1,11 +1,13 @@
 extern crate stick_solo;
 use bevy::prelude::*;
 use image::{Rgb, RgbImage};
+use ndarray::prelude::*;
 use std::ops::RangeInclusive;
 use std::{env, fs::File, io::BufReader};
 use stick_solo::act::one_holding_switchable_nr_couple::OneHoldingSwitchableNRCouple;
+use stick_solo::plan::cross_entropy_optimizing::encoding::Feature;
 use stick_solo::plan::cross_entropy_optimizing::experiment::Experiment;
-use stick_solo::plan::cross_entropy_optimizing::utils::{decode, encode};
+use stick_solo::plan::seeded_rng;
 
 fn main() {
     let args = env::args();
@@ -47,17 +49,30 @@ fn sample_holding_goals(
     assert!(yrange.0 < yrange.1, "Bad range argument.");
     assert!(scale > 0.0, "Bad scale argument.");
     let world = experiment.world;
+    let mut rng = seeded_rng(0);
     let agent = OneHoldingSwitchableNRCouple::new(
         &world.holding_side,
         Vec2::new(0.0, 0.0),
         &world.holding_ls,
-        &world.sample_holding_qs(),
+        &world.sample_holding_qs(&mut rng),
         &world.holding_q_clamps(),
+        world.holding_links.as_deref(),
+        world.holding_joint_limits.clone(),
         &world.non_holding_ls,
-        &world.sample_non_holding_qs(),
+        &world.sample_non_holding_qs(&mut rng),
         &world.non_holding_q_clamps(),
+        world.non_holding_links.as_deref(),
+        world.non_holding_joint_limits.clone(),
     );
-    let fcn = experiment.fcn;
+    let fcn = experiment.policy;
+    let (encoder, decoder) = (experiment.encoder, experiment.decoder);
+    let goal_range = encoder
+        .range(
+            Feature::RelativeGoal,
+            world.holding_ls.len(),
+            world.non_holding_ls.len(),
+        )
+        .expect("Encoder without a relative goal.");
     println!(
         "x = {:?};",
         RangeInclusive::new(xrange.0, xrange.1)
@@ -83,34 +98,45 @@ fn sample_holding_goals(
         (xrange.1 - xrange.0 + 1) as u32,
         (yrange.1 - yrange.0 + 1) as u32,
     );
-    for x in RangeInclusive::new(xrange.0, xrange.1) {
-        for y in RangeInclusive::new(yrange.0, yrange.1) {
-            let (x, y) = (x as f32 / scale, y as f32 / scale);
-            let non_holding_goal = Vec2::new(x, y);
-            // Network pipeline
-            let (input, fp_scale) = encode(&agent, &non_holding_goal);
-            let forward_pass = fcn.at(&input);
-            // Storing in vecs
-            let input = Vec2::new(input[4], input[5]);
-            let output = Vec2::new(forward_pass[0], forward_pass[1]);
-            o.push((input, output));
-            d.push(output.length());
-            let output = output.normalize();
-            a.push(output[0]);
-            b.push(output[1]);
-            // Image
-            original.put_pixel(
-                (x * scale - xrange.0 as f32) as u32,
-                (yrange.1 as f32 - y * scale) as u32,
-                Rgb(pixel(x, y, x, y)),
-            );
-            let output = decode(&forward_pass, fp_scale, Vec2::new(0.0, 0.0));
-            mapped.put_pixel(
-                (x * scale - xrange.0 as f32) as u32,
-                (yrange.1 as f32 - y * scale) as u32,
-                Rgb(pixel(output[0], output[1], x, y)),
-            );
-        }
+    // Network pipeline, batched over the whole grid
+    let grid = RangeInclusive::new(xrange.0, xrange.1)
+        .flat_map(|x| {
+            RangeInclusive::new(yrange.0, yrange.1)
+                .map(move |y| (x as f32 / scale, y as f32 / scale))
+        })
+        .collect::<Vec<(f32, f32)>>();
+    let encodings = grid
+        .iter()
+        .map(|&(x, y)| encoder.encode(&agent, &Vec2::new(x, y), &[]))
+        .collect::<Vec<(Array1<f32>, f32)>>();
+    let mut inputs = Array2::zeros((encodings.len(), encodings[0].0.len()));
+    for (i, (input, _)) in encodings.iter().enumerate() {
+        inputs.row_mut(i).assign(input);
+    }
+    let forward_passes = fcn.at_batch(&inputs);
+    for (i, &(x, y)) in grid.iter().enumerate() {
+        let (input, fp_scale) = &encodings[i];
+        let forward_pass = forward_passes.row(i).to_owned();
+        // Storing in vecs
+        let input = Vec2::new(input[goal_range.start], input[goal_range.start + 1]);
+        let output = Vec2::new(forward_pass[0], forward_pass[1]);
+        o.push((input, output));
+        d.push(output.length());
+        let output = output.normalize();
+        a.push(output[0]);
+        b.push(output[1]);
+        // Image
+        original.put_pixel(
+            (x * scale - xrange.0 as f32) as u32,
+            (yrange.1 as f32 - y * scale) as u32,
+            Rgb(pixel(x, y, x, y)),
+        );
+        let output = decoder.decode(&forward_pass, *fp_scale, &agent);
+        mapped.put_pixel(
+            (x * scale - xrange.0 as f32) as u32,
+            (yrange.1 as f32 - y * scale) as u32,
+            Rgb(pixel(output[0], output[1], x, y)),
+        );
     }
     println!("a = {:?};", a);
     println!("b = {:?};", b);
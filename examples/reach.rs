@@ -1,17 +1,17 @@
 extern crate stick_solo;
 use bevy::asset::AssetServerSettings;
 use bevy::{prelude::*, sprite::MaterialMesh2dBundle};
-use ndarray::{Array, Array1};
+use std::{env, fs::File, io::BufReader};
+use stick_solo::act::collision::SelfCollision;
 use stick_solo::act::switchable_nr::{Side, SwitchableNR};
 use stick_solo::game::{
     pause_plugin::Pause,
     pause_plugin::PausePlugin,
     status_bar_plugin::{StatusBarPlugin, Ticks},
 };
+use stick_solo::plan::cross_entropy_optimizing::experiment::Experiment;
 use stick_solo::plan::gradient_descent::*;
-use stick_solo::plan::random_sampling::{
-    from_current_state_random_sample_optimizer, no_prior_random_sample_optimizer,
-};
+use stick_solo::plan::ik_solver::*;
 use stick_solo::AxesHuggingUnitSquare;
 
 #[derive(Component)]
@@ -20,14 +20,22 @@ struct Goal;
 struct Edge(usize);
 #[derive(Component)]
 struct CenterOfMass;
+/// Experiment of a 2 + 2 link couple, as saved by `reach_ohc_nn_ceo`, to reach with on key 6.
+struct ExperimentPath(Option<String>);
 
-struct NPRSGoalQs(Array1<f32>);
-struct CSRSGoalQs(Array1<f32>);
-
-enum Algorithm {
-    GradientDescent,
-    NoPriorRandomSampleWithGradientDescent,
-    CurrentStateRandomSampleWithGradientDescent,
+fn gradient_descent_solver() -> Box<dyn IkSolver> {
+    Box::new(GradientDescent {
+        end_control: EndControl::JacobianTranspose,
+        com_x_goal_type: COMXGoalType::PivotGoalMidpoint,
+        com_x_weight: 0.2,
+        com_y_weight: 0.0,
+        // Keeps the folded links a few pixels apart
+        self_collision: Some(SelfCollision {
+            thickness: 8.0,
+            weight: 1.0,
+        }),
+        step_size: None,
+    })
 }
 
 fn main() {
@@ -62,11 +70,12 @@ fn main() {
             (-pi * 0.5, pi),
             (0.0, pi * 0.5),
         ],
+        None,
+        None,
         Side::Left,
     ))
-    .insert_resource(NPRSGoalQs(Array::zeros(4)))
-    .insert_resource(CSRSGoalQs(Array::zeros(4)))
-    .insert_resource(Algorithm::GradientDescent)
+    .insert_resource(gradient_descent_solver())
+    .insert_resource(ExperimentPath(env::args().nth(1)))
     .add_startup_system(init)
     .add_system(place_goal)
     .add_system(control)
@@ -120,13 +129,9 @@ fn init(
 fn place_goal(
     mouse_button_input: Res<Input<MouseButton>>,
     mut windows: ResMut<Windows>,
-    agent: Res<SwitchableNR>,
-    mut nprs_goal_qs: ResMut<NPRSGoalQs>,
-    mut csrs_goal_qs: ResMut<CSRSGoalQs>,
     mut transforms: Query<&mut Transform>,
     goal: Query<(Entity, &Goal)>,
     mut ticks: ResMut<Ticks>,
-    algorithm: Res<Algorithm>,
 ) {
     let (goal, _) = goal.single();
     let mut goal_transform = transforms.get_mut(goal).unwrap();
@@ -145,42 +150,6 @@ fn place_goal(
             goal_transform.translation.x = x_hat / scale_factor;
             goal_transform.translation.y = y_hat / scale_factor;
 
-            let (n, origin, ls, qs, q_clamps, pivoting_side) = agent.get_current_state();
-
-            let (_min_loss, best_q) = no_prior_random_sample_optimizer(
-                10_000,
-                origin,
-                ls,
-                qs[0],
-                pivoting_side,
-                q_clamps,
-                &Vec2::new(goal_transform.translation.x, goal_transform.translation.y),
-                |end: &Vec2, com: &Vec2, goal: &Vec2, origin: &Vec2| {
-                    5.0 * (end.clone() - goal.clone()).length()
-                        + com[1]
-                        + (com[0] - (origin[0] + goal[0]) / 2.0).abs()
-                },
-            );
-            nprs_goal_qs.0 = best_q;
-
-            let (_min_loss, best_q) = from_current_state_random_sample_optimizer(
-                10_000,
-                2.0,
-                n,
-                origin,
-                ls,
-                qs,
-                pivoting_side,
-                q_clamps,
-                &Vec2::new(goal_transform.translation.x, goal_transform.translation.y),
-                |end: &Vec2, com: &Vec2, goal: &Vec2, origin: &Vec2| {
-                    5.0 * (end.clone() - goal.clone()).length()
-                        + 5.0 * com[1]
-                        + (com[0] - (origin[0] + goal[0]) / 2.0).abs()
-                },
-            );
-            csrs_goal_qs.0 = best_q;
-
             ticks.0 = 0;
         }
     }
@@ -192,60 +161,19 @@ fn control(
     transforms: Query<&mut Transform>,
     goal: Query<(Entity, &Goal)>,
     mut ticks: ResMut<Ticks>,
-    nprs_goal_qs: Res<NPRSGoalQs>,
-    csrs_goal_qs: Res<CSRSGoalQs>,
-    algorithm: Res<Algorithm>,
+    mut solver: ResMut<Box<dyn IkSolver>>,
 ) {
     // Pause => pause everything
     if pause.0 {
         return;
     }
-    let (_, origin, ls, qs, _, _) = agent.get_current_state();
-
     let (goal, _) = goal.single();
     let goal_transform = transforms.get(goal).unwrap();
     let given_goal = Vec2::new(goal_transform.translation.x, goal_transform.translation.y);
 
-    let (take_end_to_given_goal, push_com_x_from_its_goal, _) = gradient_descent(
-        origin,
-        ls,
-        qs,
-        &given_goal,
-        EndControl::JacobianTranspose,
-        COMXGoalType::PivotGoalMidpoint,
-    );
-
-    match *algorithm {
-        Algorithm::GradientDescent => {
-            agent.update(take_end_to_given_goal + -0.2 * push_com_x_from_its_goal);
-        }
-        Algorithm::NoPriorRandomSampleWithGradientDescent => {
-            let alpha = 1.0 / (1.0 + ticks.0 as f32).powf(0.5);
-            let beta = 1.0 - alpha;
-            let gamma = 0.1;
-            let delta = 0.1 / (1.0 + ticks.0 as f32).powf(1.0);
-
-            let global_delta_qs = &nprs_goal_qs.0 - qs;
-            agent.update(
-                global_delta_qs, // + beta * take_end_to_given_goal
-                                 // + gamma * -push_com_x_from_its_goal
-                                 // + delta * -push_com_y_upward,
-            );
-        }
-        Algorithm::CurrentStateRandomSampleWithGradientDescent => {
-            let alpha = 1.0 / (1.0 + ticks.0 as f32).powf(0.5);
-            let beta = 1.0 - alpha;
-            let gamma = 0.1;
-            let delta = 0.1 / (1.0 + ticks.0 as f32).powf(1.0);
-
-            let global_delta_qs = &csrs_goal_qs.0 - qs;
-            agent.update(
-                global_delta_qs, // + beta * take_end_to_given_goal
-                                 // + gamma * -push_com_x_from_its_goal
-                                 // + delta * -push_com_y_upward,
-            );
-        }
-    }
+    let (_, _, _, qs, _, _) = agent.get_current_state();
+    let delta_qs = solver.solve(&agent, &given_goal).into_delta_qs(qs);
+    agent.update(delta_qs);
 
     ticks.0 += 1;
 }
@@ -272,14 +200,57 @@ fn flush_transforms(
     }
 }
 
-fn change_algorithm(input: Res<Input<KeyCode>>, mut algorithm: ResMut<Algorithm>) {
+fn change_algorithm(
+    input: Res<Input<KeyCode>>,
+    experiment_path: Res<ExperimentPath>,
+    mut solver: ResMut<Box<dyn IkSolver>>,
+) {
     if input.just_pressed(KeyCode::Key1) {
-        *algorithm = Algorithm::GradientDescent;
+        *solver = gradient_descent_solver();
     }
     if input.just_pressed(KeyCode::Key2) {
-        *algorithm = Algorithm::NoPriorRandomSampleWithGradientDescent;
+        *solver = Box::new(NoPriorRandomSampling::new(
+            10_000,
+            0,
+            Box::new(|end: &Vec2, com: &Vec2, goal: &Vec2, origin: &Vec2| {
+                5.0 * (*end - *goal).length()
+                    + com[1]
+                    + (com[0] - (origin[0] + goal[0]) / 2.0).abs()
+            }),
+        ));
     }
     if input.just_pressed(KeyCode::Key3) {
-        *algorithm = Algorithm::CurrentStateRandomSampleWithGradientDescent;
+        *solver = Box::new(FromCurrentStateRandomSampling::new(
+            10_000,
+            0,
+            2.0,
+            Box::new(|end: &Vec2, com: &Vec2, goal: &Vec2, origin: &Vec2| {
+                5.0 * (*end - *goal).length()
+                    + 5.0 * com[1]
+                    + (com[0] - (origin[0] + goal[0]) / 2.0).abs()
+            }),
+        ));
+    }
+    if input.just_pressed(KeyCode::Key4) {
+        *solver = Box::new(Fabrik {
+            max_iterations: 10,
+            tolerance: 1.0,
+        });
+    }
+    if input.just_pressed(KeyCode::Key5) {
+        *solver = Box::new(CyclicCoordinateDescent {
+            max_iterations: 10,
+            tolerance: 1.0,
+        });
+    }
+    if input.just_pressed(KeyCode::Key6) {
+        match &experiment_path.0 {
+            Some(path) => {
+                let reader = BufReader::new(File::open(path).unwrap());
+                let experiment: Experiment = serde_json::from_reader(reader).unwrap();
+                *solver = Box::new(LearnedPolicy::new(experiment, 0));
+            }
+            None => warn!("No experiment given on the command line."),
+        }
     }
 }
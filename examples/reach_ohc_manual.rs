@@ -43,9 +43,13 @@ fn main() {
                 &[0.2, 0.3],
                 &[-0.1, -0.2],
                 &[(-inf, inf), (-pi, 0.0)],
+                None,
+                None,
                 &[0.2, 0.2, 0.1],
                 &[-0.1, -0.1, -0.1],
                 &[(-inf, inf), (-pi, 0.0), (-pi / 6.0, 0.0)],
+                None,
+                None,
             ),
         ))
         .add_plugin(GoalCouplePlugin::new(GoalCouple(
@@ -104,25 +108,34 @@ fn random_sample_solve(
         };
         let (_min_loss, best_q) = no_prior_random_sample_optimizer(
             10_000,
+            0,
             origin_holding,
             ls,
+            agent.holding().get_links(),
             qs[0],
             pivoting_side,
             q_clamps,
             &goal_couple.0,
+            None,
+            None,
             loss_fn,
         );
         goal_qs.0 = best_q;
-        let (origin_non_holding, _) = get_end_verticex_and_com(origin_holding, ls, &goal_qs.0);
+        let (origin_non_holding, _) =
+            get_end_verticex_and_com(origin_holding, ls, agent.holding().get_links(), &goal_qs.0);
         let (_, _, ls, qs, q_clamps, pivoting_side) = agent.non_holding().get_current_state();
         let (_min_loss, best_q) = no_prior_random_sample_optimizer(
             10_000,
+            1,
             &origin_non_holding,
             ls,
+            agent.non_holding().get_links(),
             qs[0],
             pivoting_side,
             q_clamps,
             &goal_couple.1,
+            None,
+            None,
             loss_fn,
         );
         goal_qs.1 = best_q;
@@ -148,6 +161,7 @@ fn control(
             gradient_descent(
                 origin,
                 ls,
+                agent.holding().get_links(),
                 qs,
                 &goal_couple.0,
                 EndControl::JacobianTranspose,
@@ -169,6 +183,7 @@ fn control(
             gradient_descent(
                 origin,
                 ls,
+                agent.non_holding().get_links(),
                 qs,
                 &goal_couple.1,
                 EndControl::JacobianTranspose,
@@ -1,10 +1,9 @@
 extern crate stick_solo;
-mod world;
 
 use bevy::asset::AssetServerSettings;
 use bevy::prelude::*;
 use ndarray::prelude::*;
-use std::{env, fs::File, io::BufReader};
+use std::{env, fs::File, io::BufReader, path::Path, path::PathBuf};
 use stick_solo::act::one_holding_switchable_nr_couple::OneHoldingSwitchableNRCouple;
 use stick_solo::act::switchable_nr::Side;
 use stick_solo::game::{
@@ -14,29 +13,43 @@ use stick_solo::game::{
     pause_plugin::PausePlugin,
     status_bar_plugin::{StatusBarPlugin, Ticks},
 };
-use stick_solo::plan::cross_entropy_optimizing::ceo::CEO;
+use stick_solo::plan::cross_entropy_optimizing::ceo::{Checkpoint, Checkpointing, CEO};
+use stick_solo::plan::cross_entropy_optimizing::cma_es::CMAES;
+use stick_solo::plan::cross_entropy_optimizing::encoding::{Decoder, Encoder};
 use stick_solo::plan::cross_entropy_optimizing::experiment::Experiment;
 use stick_solo::plan::cross_entropy_optimizing::fcn::*;
+use stick_solo::plan::cross_entropy_optimizing::reach::ReachReward;
+use stick_solo::plan::cross_entropy_optimizing::telemetry::{self, JsonLines, Observers, Stdout};
 use stick_solo::plan::cross_entropy_optimizing::utils::{
-    control, decode, encode, random_sample_solve, GoalQsCouple,
+    control, random_sample_solve, GoalQsCouple,
 };
 use stick_solo::plan::cross_entropy_optimizing::world::World;
-use world::Wrapper;
+use stick_solo::plan::seeded_rng;
 
 fn main() {
-    let args = env::args();
-    let exp = if args.len() == 1 {
+    let args = env::args().collect::<Vec<String>>();
+    let exp = if args.len() == 1 || args[1] == "--cma-es" || args[1] == "--resume" {
         // Optimize
         let pi = std::f32::consts::PI;
-        let wrapper = Wrapper(World {
-            holding_side: Side::Left,
-            origin: Vec2::new(0.0, 0.0),
-            holding_ls: vec![0.2, 0.2],
-            holding_q_clamps: vec![(None, None), (Some(-pi), Some(-0.0))],
-            non_holding_ls: vec![0.2, 0.2],
-            non_holding_q_clamps: vec![(None, None), (Some(-pi), Some(-0.0))],
-            unscaled_relative_goal_region: (Vec2::new(-0.8, -0.8), Vec2::new(0.1, 0.8)),
-        });
+        let wrapper = ReachReward {
+            world: World {
+                holding_side: Side::Left,
+                origin: Vec2::new(0.0, 0.0),
+                holding_ls: vec![0.2, 0.2],
+                holding_q_clamps: vec![(None, None), (Some(-pi), Some(-0.0))],
+                holding_links: None,
+                holding_joint_limits: None,
+                non_holding_ls: vec![0.2, 0.2],
+                non_holding_q_clamps: vec![(None, None), (Some(-pi), Some(-0.0))],
+                non_holding_links: None,
+                non_holding_joint_limits: None,
+                unscaled_relative_goal_region: (Vec2::new(-0.8, -0.8), Vec2::new(0.1, 0.8)),
+            },
+            encoder: Encoder::default(),
+            decoder: Decoder::RelativeGoal,
+            // A stateless fcn sees the same input every tick, so it plans once per episode
+            replan_every: None,
+        };
         // let wrapper = Wrapper(World {
         //     holding_side: Side::Left,
         //     origin: Vec2::new(0.0, 0.0),
@@ -46,30 +59,100 @@ fn main() {
         //     non_holding_q_clamps: vec![(None, None), (Some(0.0), Some(pi))],
         //     unscaled_relative_goal_region: (Vec2::new(-0.1, -0.8), Vec2::new(0.8, 0.8)),
         // });
-        let mut fcn = FCN::new(vec![
-            (
-                wrapper.0.holding_ls.len() + wrapper.0.non_holding_ls.len() + 2,
-                Activation::Linear,
-            ),
-            (16, Activation::LeakyReLu(0.1)),
-            (16, Activation::LeakyReLu(0.1)),
-            (2, Activation::Linear),
-        ]);
-        let ceo = CEO {
-            generations: 500,
-            batch_size: 50,
-            num_episodes: 20,
-            num_episode_ticks: 200,
-            elite_frac: 0.25,
-            initial_std: 1.0,
-            noise_factor: 1.0,
-            ..Default::default()
+        // Holding goals, relative to the holding origin and scaled like the encoding, within reach
+        let reach = {
+            let holding_l: f32 = wrapper.world.holding_ls.iter().sum();
+            let non_holding_l: f32 = wrapper.world.non_holding_ls.iter().sum();
+            holding_l / (holding_l + non_holding_l)
         };
-        let (mean_reward, _th_std) = ceo.optimize(&mut fcn, &wrapper).unwrap();
-        let exp = Experiment {
-            fcn: fcn,
-            ceo: ceo,
-            world: wrapper.0,
+        let mut fcn = FCN::new(
+            vec![
+                (
+                    wrapper.encoder.len(
+                        wrapper.world.holding_ls.len(),
+                        wrapper.world.non_holding_ls.len(),
+                    ),
+                    Activation::Linear,
+                ),
+                (16, Activation::LeakyReLu(0.1)),
+                (16, Activation::LeakyReLu(0.1)),
+                (
+                    wrapper.decoder.output_dof(),
+                    Activation::Bounded(vec![(-reach, reach); wrapper.decoder.output_dof()]),
+                ),
+            ],
+            Initializer::HeNormal,
+            &mut seeded_rng(0),
+        );
+        let (mean_reward, exp) = if args.len() == 1 || args[1] == "--resume" {
+            let ceo = CEO {
+                generations: 500,
+                batch_size: 50,
+                num_episodes: 20,
+                num_episode_ticks: 200,
+                elite_frac: 0.25,
+                initial_std: 1.0,
+                noise_factor: 1.0,
+                ..Default::default()
+            };
+            let checkpointing = Checkpointing {
+                path: PathBuf::from("ceo_checkpoint.json"),
+                every: 10,
+            };
+            let resuming = args.len() != 1;
+            let telemetry = telemetry::open(Path::new("ceo_telemetry.jsonl"), resuming).unwrap();
+            let mut observer =
+                Observers(vec![Box::new(Stdout), Box::new(JsonLines::new(telemetry))]);
+            let (mean_reward, _th_std, stop_reason) = if args.len() == 1 {
+                ceo.optimize_with_checkpoints(&mut fcn, &wrapper, &mut observer, &checkpointing)
+            } else {
+                if args.len() != 3 {
+                    panic!("Bad cmd line parameters.");
+                }
+                let checkpoint = Checkpoint::load(&PathBuf::from(&args[2])).unwrap();
+                ceo.resume(
+                    &mut fcn,
+                    &wrapper,
+                    &mut observer,
+                    checkpoint,
+                    Some(&checkpointing),
+                )
+            }
+            .unwrap();
+            println!("Stopped: {:?}", stop_reason);
+            let exp = Experiment {
+                policy: fcn,
+                ceo: Some(ceo),
+                cma_es: None,
+                world: wrapper.world,
+                encoder: wrapper.encoder,
+                decoder: wrapper.decoder,
+            };
+            (mean_reward, exp)
+        } else {
+            let cma_es = CMAES {
+                generations: 500,
+                batch_size: 50,
+                num_episodes: 20,
+                num_episode_ticks: 200,
+                initial_step_size: 1.0,
+                ..Default::default()
+            };
+            let telemetry = File::create("cma_es_telemetry.jsonl").unwrap();
+            let mut observer =
+                Observers(vec![Box::new(Stdout), Box::new(JsonLines::new(telemetry))]);
+            let (mean_reward, _th_std, stop_reason) =
+                cma_es.optimize(&mut fcn, &wrapper, &mut observer).unwrap();
+            println!("Stopped: {:?}", stop_reason);
+            let exp = Experiment {
+                policy: fcn,
+                ceo: None,
+                cma_es: Some(cma_es),
+                world: wrapper.world,
+                encoder: wrapper.encoder,
+                decoder: wrapper.decoder,
+            };
+            (mean_reward, exp)
         };
         // Save
         use chrono::{Datelike, Timelike, Utc};
@@ -92,7 +175,6 @@ fn main() {
             panic!("Bad cmd line parameters.");
         }
         // Load from file
-        let args = args.collect::<Vec<String>>();
         let file = File::open(&args[1]).unwrap();
         let reader = BufReader::new(file);
         let exp: Experiment = serde_json::from_reader(reader).unwrap();
@@ -102,6 +184,7 @@ fn main() {
 
     // Visualize
     let world = exp.world.clone();
+    let mut rng = seeded_rng(0);
     App::new()
         .insert_resource(ClearColor(Color::rgb(0.0, 0.0, 0.0)))
         .insert_resource(AssetServerSettings {
@@ -120,23 +203,29 @@ fn main() {
             });
         })
         .add_plugin(exp.world)
-        .insert_resource(exp.fcn)
+        .insert_resource(exp.policy)
+        .insert_resource(exp.encoder)
+        .insert_resource(exp.decoder)
         .insert_resource(GoalQsCouple(Array::zeros(0), Array::zeros(0)))
         .add_plugin(OneHoldingSwitchableNRCouplePlugin::new(
             OneHoldingSwitchableNRCouple::new(
                 &world.holding_side,
                 world.origin,
                 &world.holding_ls,
-                &world.sample_holding_qs(),
+                &world.sample_holding_qs(&mut rng),
                 &world.holding_q_clamps(),
+                world.holding_links.as_deref(),
+                world.holding_joint_limits.clone(),
                 &world.non_holding_ls,
-                &world.sample_non_holding_qs(),
+                &world.sample_non_holding_qs(&mut rng),
                 &world.non_holding_q_clamps(),
+                world.non_holding_links.as_deref(),
+                world.non_holding_joint_limits.clone(),
             ),
         ))
         .add_plugin(GoalCouplePlugin::new(GoalCouple(
             Vec2::new(0.0, 0.0),
-            world.sample_goal(),
+            world.sample_goal(&mut rng),
         )))
         .add_plugin(StatusBarPlugin)
         .add_plugin(PausePlugin)
@@ -151,16 +240,17 @@ fn set_goal_qs_couple(
     goal_qs_couple: &mut GoalQsCouple,
     goal_couple: &mut GoalCouple,
     fcn: &FCN,
+    encoder: &Encoder,
+    decoder: &Decoder,
 ) {
-    let holding_origin = agent.holding().get_current_state().1.clone();
     let non_holding_goal = goal_couple.1;
     // Network pipeline
-    let (input, scale) = encode(&agent, &non_holding_goal);
+    let (input, scale) = encoder.encode(agent, &non_holding_goal, &[]);
     let forward_pass = fcn.at(&input);
-    let holding_goal = decode(&forward_pass, scale, holding_origin);
+    let holding_goal = decoder.decode(&forward_pass, scale, agent);
     // Setting GoalCouple and GoalQsCouple
     *goal_couple = GoalCouple(holding_goal, non_holding_goal);
-    random_sample_solve(agent, goal_couple, goal_qs_couple);
+    random_sample_solve(agent, goal_couple, goal_qs_couple, 0);
 }
 
 fn initial_set_goal_qs_couple_system(
@@ -168,8 +258,17 @@ fn initial_set_goal_qs_couple_system(
     mut goal_qs_couple: ResMut<GoalQsCouple>,
     mut goal_couple: ResMut<GoalCouple>,
     fcn: Res<FCN>,
+    encoder: Res<Encoder>,
+    decoder: Res<Decoder>,
 ) {
-    set_goal_qs_couple(&agent, &mut goal_qs_couple, &mut goal_couple, &fcn);
+    set_goal_qs_couple(
+        &agent,
+        &mut goal_qs_couple,
+        &mut goal_couple,
+        &fcn,
+        &encoder,
+        &decoder,
+    );
 }
 
 fn interactive_set_goal_qs_couple_system(
@@ -178,6 +277,8 @@ fn interactive_set_goal_qs_couple_system(
     mut ticks: ResMut<Ticks>,
     mut goal_couple: ResMut<GoalCouple>,
     fcn: Res<FCN>,
+    encoder: Res<Encoder>,
+    decoder: Res<Decoder>,
     keyboard_input: Res<Input<KeyCode>>,
 ) {
     if keyboard_input.pressed(KeyCode::I)
@@ -185,7 +286,14 @@ fn interactive_set_goal_qs_couple_system(
         || keyboard_input.pressed(KeyCode::J)
         || keyboard_input.pressed(KeyCode::L)
     {
-        set_goal_qs_couple(&agent, &mut goal_qs_couple, &mut goal_couple, &fcn);
+        set_goal_qs_couple(
+            &agent,
+            &mut goal_qs_couple,
+            &mut goal_couple,
+            &fcn,
+            &encoder,
+            &decoder,
+        );
         ticks.0 = 0;
     }
 }
@@ -203,88 +311,3 @@ fn control_system(
     control(&mut agent, &goal_qs_couple, &goal_couple, ticks.0);
     ticks.0 += 1;
 }
-
-use ndarray::prelude::*;
-use stick_solo::act::one_holding_switchable_nr_couple::OneHoldingSwitchableNRCouple;
-use stick_solo::act::switchable_nr::SwitchableNR;
-use stick_solo::game::goal_couple_plugin::GoalCouple;
-use stick_solo::plan::cross_entropy_optimizing::ceo::Reward;
-use stick_solo::plan::cross_entropy_optimizing::fcn::*;
-use stick_solo::plan::cross_entropy_optimizing::utils::{
-    control, decode, encode, random_sample_solve, GoalQsCouple,
-};
-use stick_solo::plan::cross_entropy_optimizing::world::World;
-
-pub struct Wrapper(pub World);
-
-impl Reward for Wrapper {
-    fn average_reward(
-        &self,
-        fcn: &FCN,
-        params: &Array1<f32>,
-        num_episodes: usize,
-        num_episode_ticks: usize,
-    ) -> f32 {
-        let mut cumulative_reward = 0.0;
-        for _ in 0..num_episodes {
-            // Spawn agent
-            let mut agent = OneHoldingSwitchableNRCouple::new(
-                &self.0.holding_side,
-                self.0.origin,
-                &self.0.holding_ls,
-                &self.0.sample_holding_qs(),
-                &self.0.holding_q_clamps(),
-                &self.0.non_holding_ls,
-                &self.0.sample_non_holding_qs(),
-                &self.0.non_holding_q_clamps(),
-            );
-            let holding_origin = agent.holding().get_current_state().1.clone();
-            let non_holding_goal = self.0.sample_goal();
-            // Network pipeline
-            let (input, scale) = encode(&agent, &non_holding_goal);
-            let forward_pass = fcn.at_with(&input, params);
-            let holding_goal = decode(&forward_pass, scale, holding_origin);
-            // Setting GoalCouple and GoalQsCouple
-            let goal_couple = GoalCouple(holding_goal, non_holding_goal);
-            let mut goal_qs_couple = GoalQsCouple(Array::zeros(0), Array::zeros(0));
-            random_sample_solve(&agent, &goal_couple, &mut goal_qs_couple);
-            // Start calculating reward
-            let mut episode_reward = 0.0;
-            for ticks in 0..num_episode_ticks {
-                // Apply control
-                control(&mut agent, &goal_qs_couple, &goal_couple, ticks);
-                // Holding
-                let last_vertex = agent.holding().get_last_vertex();
-                let dist = (last_vertex - holding_goal).length();
-                episode_reward -= 2.0 * dist;
-                // Non holding
-                let last_vertex = agent.non_holding().get_last_vertex();
-                let dist = (last_vertex - non_holding_goal).length();
-                episode_reward -= 10.0 * dist;
-                // COM y
-                let com = agent.get_center_of_mass();
-                episode_reward -= 5.0 * com[1];
-                // COM x
-                let com = agent.get_center_of_mass();
-                episode_reward -= (com[0] - (non_holding_goal[0] + holding_origin[0]) / 2.0).abs();
-            }
-            // Holding
-            let last_vertex = agent.holding().get_last_vertex();
-            let dist = (last_vertex - holding_goal).length();
-            if dist < SwitchableNR::GOAL_REACHED_SLACK {
-                episode_reward += 500.0;
-            }
-            // Non holding
-            let last_vertex = agent.non_holding().get_last_vertex();
-            let dist = (last_vertex - non_holding_goal).length();
-            if dist < SwitchableNR::GOAL_REACHED_SLACK {
-                episode_reward += 1000.0;
-            }
-
-            cumulative_reward += episode_reward;
-        }
-
-        let average_reward = cumulative_reward / num_episodes as f32;
-        average_reward
-    }
-}
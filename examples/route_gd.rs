@@ -9,6 +9,7 @@ use stick_solo::game::{
     status_bar_plugin::{StatusBarPlugin, Ticks},
 };
 use stick_solo::plan::gradient_descent::*;
+use stick_solo::plan::ik_solver::*;
 use stick_solo::AxesHuggingUnitSquare;
 
 #[derive(Component)]
@@ -39,8 +40,18 @@ fn main() {
             &[64.; 4],
             &[-2.0, 0.0, 2.0, 0.0],
             &[(-inf, inf); 4],
+            None,
+            None,
             Side::Left,
         ))
+        .insert_resource::<Box<dyn IkSolver>>(Box::new(GradientDescent {
+            end_control: EndControl::JacobianTranspose,
+            com_x_goal_type: COMXGoalType::PivotGoalMidpoint,
+            com_x_weight: 0.0,
+            com_y_weight: 0.0,
+            self_collision: None,
+            step_size: Some(0.03),
+        }))
         .add_plugin(PathPlugin::new(Path::default()))
         .add_plugin(StatusBarPlugin)
         .add_plugin(PausePlugin)
@@ -131,6 +142,7 @@ fn main() {
 fn control(
     mut agent: ResMut<SwitchableNR>,
     mut path: ResMut<Path>,
+    mut solver: ResMut<Box<dyn IkSolver>>,
     pause: Res<Pause>,
     mut ticks: ResMut<Ticks>,
 ) {
@@ -142,7 +154,7 @@ fn control(
     if path.0.is_empty() {
         return;
     }
-    let (_, origin, ls, qs, _, pivoting_side) = agent.get_current_state();
+    let (_, origin, _, qs, _, pivoting_side) = agent.get_current_state();
     let given_goal = path.0.front().unwrap().clone();
     let have_to_match = match pivoting_side {
         Side::Left => given_goal[0] - origin[0] < -SwitchableNR::GOAL_REACHED_SLACK,
@@ -159,30 +171,8 @@ fn control(
         return;
     }
 
-    let (take_end_to_given_goal, push_com_x_from_its_goal, push_com_y_upward) = gradient_descent(
-        origin,
-        ls,
-        qs,
-        &given_goal,
-        EndControl::JacobianTranspose,
-        COMXGoalType::PivotGoalMidpoint,
-    );
-
-    fn downward_push_coeff(com: &Vec2, origin: Vec2) -> f32 {
-        let diff_y = com[1] - origin[1];
-        if diff_y < 0.0 {
-            0.0
-        } else {
-            0.1 * diff_y.abs()
-        }
-    }
-    let beta = 0.03 / take_end_to_given_goal.mapv(|e| e * e).sum().sqrt();
-    let com = agent.get_center_of_mass();
-    let origin = origin.clone();
-    agent.update(
-        beta * take_end_to_given_goal, // + -0.2 * push_com_x_from_its_goal
-                                       // + -downward_push_coeff(&com, origin) * push_com_y_upward,
-    );
+    let delta_qs = solver.solve(&agent, &given_goal).into_delta_qs(qs);
+    agent.update(delta_qs);
 
     ticks.0 += 1;
 }
@@ -13,11 +13,13 @@ use stick_solo::game::{
     pause_plugin::PausePlugin,
     status_bar_plugin::{StatusBarPlugin, Ticks},
 };
+use stick_solo::plan::cross_entropy_optimizing::encoding::{Decoder, Encoder};
 use stick_solo::plan::cross_entropy_optimizing::experiment::Experiment;
 use stick_solo::plan::cross_entropy_optimizing::fcn::*;
 use stick_solo::plan::cross_entropy_optimizing::utils::{
-    control, decode, encode, random_sample_solve, GoalQsCouple,
+    control, random_sample_solve, GoalQsCouple,
 };
+use stick_solo::plan::seeded_rng;
 
 fn main() {
     let args = env::args();
@@ -35,6 +37,7 @@ fn main() {
 
     // Visualize
     let world = left_holding_exp.world.clone();
+    let mut rng = seeded_rng(0);
     App::new()
         .insert_resource(ClearColor(Color::rgb(0.0, 0.0, 0.0)))
         .insert_resource(AssetServerSettings {
@@ -53,8 +56,16 @@ fn main() {
             });
         })
         .insert_resource(FCNs {
-            left_holding: left_holding_exp.fcn,
-            right_holding: right_holding_exp.fcn,
+            left_holding: (
+                left_holding_exp.policy,
+                left_holding_exp.encoder,
+                left_holding_exp.decoder,
+            ),
+            right_holding: (
+                right_holding_exp.policy,
+                right_holding_exp.encoder,
+                right_holding_exp.decoder,
+            ),
         })
         .insert_resource(GoalQsCouple(Array::zeros(0), Array::zeros(0)))
         .add_plugin(OneHoldingSwitchableNRCouplePlugin::new(
@@ -62,11 +73,15 @@ fn main() {
                 &world.holding_side,
                 Vec2::new(0.0, -0.1),
                 &world.holding_ls,
-                &world.sample_holding_qs(),
+                &world.sample_holding_qs(&mut rng),
                 &world.holding_q_clamps(),
+                world.holding_links.as_deref(),
+                world.holding_joint_limits.clone(),
                 &world.non_holding_ls,
-                &world.sample_non_holding_qs(),
+                &world.sample_non_holding_qs(&mut rng),
                 &world.non_holding_q_clamps(),
+                world.non_holding_links.as_deref(),
+                world.non_holding_joint_limits.clone(),
             ),
         ))
         .add_plugin(GoalCouplePlugin::new(GoalCouple(
@@ -82,8 +97,8 @@ fn main() {
 }
 
 struct FCNs {
-    left_holding: FCN,
-    right_holding: FCN,
+    left_holding: (FCN, Encoder, Decoder),
+    right_holding: (FCN, Encoder, Decoder),
 }
 
 fn set_goal_qs_couple(
@@ -94,17 +109,19 @@ fn set_goal_qs_couple(
     path: &Path,
 ) {
     let non_holding_goal = path.0.front().unwrap().clone();
-    let (_, holding_origin, _, _, _, holding_side) = agent.holding().get_current_state();
-    // Network pipeline
-    let (input, scale) = encode(&agent, &non_holding_goal);
-    let forward_pass = match holding_side {
-        Side::Left => fcns.left_holding.at(&input),
-        Side::Right => fcns.right_holding.at(&input),
+    let (_, _, _, _, _, holding_side) = agent.holding().get_current_state();
+    let (fcn, encoder, decoder) = match holding_side {
+        Side::Left => &fcns.left_holding,
+        Side::Right => &fcns.right_holding,
     };
-    let holding_goal = decode(&forward_pass, scale, holding_origin.clone());
+    // Network pipeline
+    let next_holds = path.0.iter().skip(1).copied().collect::<Vec<Vec2>>();
+    let (input, scale) = encoder.encode(agent, &non_holding_goal, &next_holds);
+    let forward_pass = fcn.at(&input);
+    let holding_goal = decoder.decode(&forward_pass, scale, agent);
     // Setting GoalCouple and GoalQsCouple
     *goal_couple = GoalCouple(holding_goal, non_holding_goal);
-    random_sample_solve(agent, goal_couple, goal_qs_couple);
+    random_sample_solve(agent, goal_couple, goal_qs_couple, path.0.len() as u64);
 }
 
 fn initial_set_goal_qs_couple_system(
@@ -9,6 +9,7 @@ use stick_solo::game::{
     status_bar_plugin::{StatusBarPlugin, Ticks},
 };
 use stick_solo::plan::gradient_descent::*;
+use stick_solo::plan::ik_solver::*;
 use stick_solo::AxesHuggingUnitSquare;
 
 #[derive(Component)]
@@ -35,6 +36,7 @@ fn main() {
             commands.spawn_bundle(Camera2dBundle::default());
         })
         .insert_resource(RestTicks(0))
+        .insert_resource(resting_solver())
         .insert_resource(SwitchableNR::new(
             Vec2::new(0.0, -0.1),
             &[64.; 4],
@@ -45,6 +47,8 @@ fn main() {
                 (-pi * 0.5, pi),
                 (0.0, pi * 0.5),
             ],
+            None,
+            None,
             Side::Left,
         ))
         .add_plugin(PathPlugin::new(Path::default()))
@@ -136,9 +140,35 @@ fn main() {
 
 struct RestTicks(usize);
 
+const REST_TICKS: usize = 50;
+
+/// Brings the com down and under the pivot without moving the end.
+fn resting_solver() -> Box<dyn IkSolver> {
+    Box::new(GradientDescent {
+        end_control: EndControl::JacobianTranspose,
+        com_x_goal_type: COMXGoalType::PivotGoalMidpoint,
+        com_x_weight: 1.0,
+        com_y_weight: 5.0,
+        self_collision: None,
+        step_size: Some(0.0),
+    })
+}
+
+fn reaching_solver() -> Box<dyn IkSolver> {
+    Box::new(GradientDescent {
+        end_control: EndControl::JacobianTranspose,
+        com_x_goal_type: COMXGoalType::PivotGoalMidpoint,
+        com_x_weight: 0.2,
+        com_y_weight: 0.0,
+        self_collision: None,
+        step_size: Some(0.03),
+    })
+}
+
 fn control(
     mut agent: ResMut<SwitchableNR>,
     mut path: ResMut<Path>,
+    mut solver: ResMut<Box<dyn IkSolver>>,
     pause: Res<Pause>,
     mut ticks: ResMut<Ticks>,
     mut rest_ticks: ResMut<RestTicks>,
@@ -151,7 +181,7 @@ fn control(
     if path.0.is_empty() {
         return;
     }
-    let (_, origin, ls, qs, _, pivoting_side) = agent.get_current_state();
+    let (_, origin, _, qs, _, pivoting_side) = agent.get_current_state();
     let given_goal = path.0.front().unwrap().clone();
     let have_to_match = match pivoting_side {
         Side::Left => given_goal[0] - origin[0] < -SwitchableNR::GOAL_REACHED_SLACK,
@@ -166,36 +196,15 @@ fn control(
         agent.switch_pivot();
         path.0.pop_front();
         rest_ticks.0 = 0;
+        *solver = resting_solver();
         return;
     }
-
-    let (take_end_to_given_goal, push_com_x_from_its_goal, push_com_y_upward) = gradient_descent(
-        origin,
-        ls,
-        qs,
-        &given_goal,
-        EndControl::JacobianTranspose,
-        COMXGoalType::PivotGoalMidpoint,
-    );
-
-    fn downward_push_coeff(com: &Vec2, origin: Vec2) -> f32 {
-        let diff_y = com[1] - origin[1];
-        if diff_y < 0.0 {
-            0.0
-        } else {
-            0.1 * diff_y.abs()
-        }
+    if rest_ticks.0 == REST_TICKS {
+        *solver = reaching_solver();
     }
-    let beta = 0.03 / take_end_to_given_goal.mapv(|e| e * e).sum().sqrt();
-    let com = agent.get_center_of_mass();
-    let origin = origin.clone();
-    agent.update(if rest_ticks.0 < 50 {
-        -1.0 * push_com_x_from_its_goal + -5.0 * push_com_y_upward
-    } else {
-        beta * take_end_to_given_goal
-            + -0.2 * push_com_x_from_its_goal
-            + -downward_push_coeff(&com, origin) * push_com_y_upward
-    });
+
+    let delta_qs = solver.solve(&agent, &given_goal).into_delta_qs(qs);
+    agent.update(delta_qs);
 
     ticks.0 += 1;
     rest_ticks.0 += 1;
@@ -0,0 +1,154 @@
+extern crate stick_solo;
+use rayon::prelude::*;
+use serde_json::Value;
+use std::{
+    env, fs, fs::File, io::BufReader, io::Write, num::NonZeroUsize, path::Path, path::PathBuf,
+};
+use stick_solo::plan::cross_entropy_optimizing::ceo::StopReason;
+use stick_solo::plan::cross_entropy_optimizing::experiment::Experiment;
+use stick_solo::plan::cross_entropy_optimizing::reach::ReachReward;
+use stick_solo::plan::cross_entropy_optimizing::sweep::{Sweep, Trial};
+use stick_solo::plan::cross_entropy_optimizing::telemetry::{
+    GenerationRecord, JsonLines, Observer,
+};
+use stick_solo::plan::{derive_seed, seeded_rng};
+
+/// Trains every trial of a sweep over a base experiment with its ceo on the reach reward,
+/// without any window.
+/// Usage: sweep <base experiment> <sweep> <output dir> [--replan-every N] [--keep N]
+/// Writes each trial's telemetry, a results table ranked by the reward of the final params,
+/// and the best `keep` (default 3) trained experiments to the output dir.
+fn main() {
+    let args = env::args().collect::<Vec<String>>();
+    if args.len() < 4 {
+        panic!("Bad cmd line parameters.");
+    }
+    let base: Value =
+        serde_json::from_reader(BufReader::new(File::open(&args[1]).unwrap())).unwrap();
+    let sweep: Sweep =
+        serde_json::from_reader(BufReader::new(File::open(&args[2]).unwrap())).unwrap();
+    let output_dir = PathBuf::from(&args[3]);
+    let mut replan_every = None;
+    let mut keep = 3;
+    let mut options = args[4..].iter();
+    while let Some(option) = options.next() {
+        let value = options
+            .next()
+            .expect("Bad cmd line parameters.")
+            .parse::<usize>()
+            .unwrap();
+        match option.as_str() {
+            "--replan-every" => {
+                replan_every =
+                    Some(NonZeroUsize::new(value).expect("Replan interval must be positive."))
+            }
+            "--keep" => keep = value,
+            _ => panic!("Bad cmd line parameters."),
+        }
+    }
+    fs::create_dir_all(&output_dir).unwrap();
+
+    let trials = sweep.trials();
+    println!("Running {} trials", trials.len());
+    let mut results = trials
+        .par_iter()
+        .map(|trial| {
+            let (experiment, record, stop_reason) =
+                run_trial(trial, &base, replan_every, &output_dir);
+            (trial, experiment, record, stop_reason)
+        })
+        .collect::<Vec<(&Trial, Experiment, GenerationRecord, StopReason)>>();
+    // Best final params first, diverged trials last
+    results.sort_by(|a, b| {
+        let (a, b) = (a.2.current_params_reward, b.2.current_params_reward);
+        a.is_nan().cmp(&b.is_nan()).then(b.total_cmp(&a))
+    });
+
+    // Results table
+    let mut header = vec![
+        "trial".to_string(),
+        "current_params_reward".to_string(),
+        "mean_reward".to_string(),
+        "generations".to_string(),
+        "stop_reason".to_string(),
+        "wall_time".to_string(),
+    ];
+    header.extend(sweep.parameters.iter().map(|p| p.pointer.clone()));
+    let mut rows = vec![header];
+    for (trial, _, record, stop_reason) in &results {
+        let mut row = vec![
+            trial.index.to_string(),
+            record.current_params_reward.to_string(),
+            record.mean_reward.to_string(),
+            record.generation.to_string(),
+            format!("{:?}", stop_reason),
+            record.wall_time.to_string(),
+        ];
+        row.extend(trial.assignments.iter().map(|(_, value)| value.to_string()));
+        rows.push(row);
+    }
+    let mut csv = File::create(output_dir.join("results.csv")).unwrap();
+    for row in &rows {
+        println!("{}", row.join("\t"));
+        let fields = row
+            .iter()
+            .map(|field| csv_field(field))
+            .collect::<Vec<String>>();
+        writeln!(csv, "{}", fields.join(",")).unwrap();
+    }
+
+    // Best experiments
+    for (rank, (trial, experiment, _, _)) in results.iter().take(keep).enumerate() {
+        let path = output_dir.join(format!("best_{}_trial_{}.json", rank + 1, trial.index));
+        serde_json::to_writer_pretty(&File::create(&path).unwrap(), experiment).unwrap();
+        println!("Saved {}", path.display());
+    }
+}
+
+/// Trains from fresh params, with the ceo and params seeded by the trial.
+fn run_trial(
+    trial: &Trial,
+    base: &Value,
+    replan_every: Option<NonZeroUsize>,
+    output_dir: &Path,
+) -> (Experiment, GenerationRecord, StopReason) {
+    let mut experiment: Experiment = serde_json::from_value(trial.apply(base))
+        .unwrap_or_else(|e| panic!("Bad experiment for trial {}: {}", trial.index, e));
+    let mut ceo = experiment.ceo.take().expect("Base experiment without ceo.");
+    ceo.seed = derive_seed(ceo.seed, trial.seed);
+    experiment
+        .policy
+        .reinitialize(&mut seeded_rng(derive_seed(trial.seed, 0)));
+    let reward = ReachReward {
+        world: experiment.world.clone(),
+        encoder: experiment.encoder.clone(),
+        decoder: experiment.decoder,
+        replan_every,
+    };
+    let telemetry = File::create(output_dir.join(format!("trial_{}.jsonl", trial.index))).unwrap();
+    let mut telemetry = JsonLines::new(telemetry);
+    let mut last_record = None;
+    let (_, _, stop_reason) = {
+        let mut observer = |record: &GenerationRecord| {
+            last_record = Some(record.clone());
+            telemetry.observe(record)
+        };
+        ceo.optimize(&mut experiment.policy, &reward, &mut observer)
+            .unwrap()
+    };
+    let record = last_record.expect("Trial ran no generations.");
+    println!(
+        "trial={} current_params_reward={:?} stop_reason={:?}",
+        trial.index, record.current_params_reward, stop_reason
+    );
+    experiment.ceo = Some(ceo);
+    (experiment, record, stop_reason)
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains(',') || field.contains('"') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
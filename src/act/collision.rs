@@ -0,0 +1,168 @@
+use bevy::prelude::*;
+use ndarray::prelude::*;
+
+/// Closest points, (on the first, on the second), of the segments [p1, q1] and [p2, q2].
+pub fn closest_points(p1: Vec2, q1: Vec2, p2: Vec2, q2: Vec2) -> (Vec2, Vec2) {
+    let (d1, d2, r) = (q1 - p1, q2 - p2, p1 - p2);
+    let (a, e, f) = (d1.dot(d1), d2.dot(d2), d2.dot(r));
+    let (s, t) = if a <= f32::EPSILON && e <= f32::EPSILON {
+        (0.0, 0.0)
+    } else if a <= f32::EPSILON {
+        (0.0, (f / e).clamp(0.0, 1.0))
+    } else {
+        let c = d1.dot(r);
+        if e <= f32::EPSILON {
+            ((-c / a).clamp(0.0, 1.0), 0.0)
+        } else {
+            // Closest points of the lines, clamped to the first segment, then to the second
+            let b = d1.dot(d2);
+            let denominator = a * e - b * b;
+            let s = if denominator > 0.0 {
+                ((b * f - c * e) / denominator).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let t = (b * s + f) / e;
+            if t < 0.0 {
+                ((-c / a).clamp(0.0, 1.0), 0.0)
+            } else if t > 1.0 {
+                (((b - c) / a).clamp(0.0, 1.0), 1.0)
+            } else {
+                (s, t)
+            }
+        }
+    };
+    (p1 + s * d1, p2 + t * d2)
+}
+
+/// (i, k, clearance) of every pair i < k of non adjacent links of the chain with these vertices.
+/// The clearance is the distance between the surfaces of the links, each `thickness` across, and
+/// is negative when they overlap. Adjacent links always touch at their shared joint, so they are
+/// not checked. Links j - 1 and j fold onto each other as q_j nears ±PI, which nothing here
+/// penalizes; only q clamps of joint j well inside ±PI rule it out.
+pub fn link_clearances(vertices: &[Vec2], thickness: f32) -> Vec<(usize, usize, f32)> {
+    let n = vertices.len() - 1;
+    let mut clearances = vec![];
+    for i in 0..n {
+        for k in (i + 2)..n {
+            let (a, b) = closest_points(vertices[i], vertices[i + 1], vertices[k], vertices[k + 1]);
+            clearances.push((i, k, (a - b).length() - thickness));
+        }
+    }
+    clearances
+}
+
+/// The pairs of link_clearances that overlap.
+pub fn self_collisions(vertices: &[Vec2], thickness: f32) -> Vec<(usize, usize, f32)> {
+    link_clearances(vertices, thickness)
+        .into_iter()
+        .filter(|(_, _, clearance)| *clearance < 0.0)
+        .collect()
+}
+
+/// Sum of the squared overlaps of the links; zero iff no links collide.
+pub fn self_collision_penalty(vertices: &[Vec2], thickness: f32) -> f32 {
+    self_collisions(vertices, thickness)
+        .iter()
+        .map(|(_, _, clearance)| clearance * clearance)
+        .sum()
+}
+
+/// Gradient of self_collision_penalty w.r.t. the qs of the chain, pushing the links apart when
+/// descended. Joint j rotates link k but not link i for i < j <= k, moving the closest point b of
+/// link k by perp(b - v_j) while the closest point a of link i stays.
+pub fn self_collision_gradient(vertices: &[Vec2], thickness: f32) -> Array1<f32> {
+    let n = vertices.len() - 1;
+    let mut gradient = Array1::zeros(n);
+    for (i, k, clearance) in self_collisions(vertices, thickness) {
+        let (a, b) = closest_points(vertices[i], vertices[i + 1], vertices[k], vertices[k + 1]);
+        // Crossing links have no closest direction, so separate their midpoints
+        let normal = if (a - b).length() > f32::EPSILON {
+            (a - b).normalize()
+        } else {
+            ((vertices[i] + vertices[i + 1]) - (vertices[k] + vertices[k + 1])).normalize_or_zero()
+        };
+        for j in (i + 1)..=k {
+            let arm = b - vertices[j];
+            // d(overlap^2)/dq_j = 2 * overlap * -d(distance)/dq_j, overlap = -clearance
+            gradient[j] += -2.0 * clearance * normal.dot(Vec2::new(-arm[1], arm[0]));
+        }
+    }
+    gradient
+}
+
+/// The self collision penalty as a weighted secondary objective, for the losses of the random
+/// sample optimizers and gradient controllers.
+#[derive(Clone, Copy)]
+pub struct SelfCollision {
+    /// Across every link.
+    pub thickness: f32,
+    pub weight: f32,
+}
+
+impl SelfCollision {
+    pub fn penalty(&self, vertices: &[Vec2]) -> f32 {
+        self.weight * self_collision_penalty(vertices, self.thickness)
+    }
+
+    pub fn gradient(&self, vertices: &[Vec2]) -> Array1<f32> {
+        self.weight * self_collision_gradient(vertices, self.thickness)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertices(ls: &[f32], qs: &[f32]) -> Vec<Vec2> {
+        let mut vertices = vec![Vec2::new(0.3, -0.1)];
+        let mut theta = 0.0;
+        for (l, q) in ls.iter().zip(qs.iter()) {
+            theta += q;
+            let last = *vertices.last().unwrap();
+            vertices.push(last + *l * Vec2::new(theta.cos(), theta.sin()));
+        }
+        vertices
+    }
+
+    #[test]
+    fn segment_distances() {
+        let distance = |p1, q1, p2, q2| {
+            let (a, b) = closest_points(p1, q1, p2, q2);
+            (a - b).length()
+        };
+        let (x, y) = (Vec2::X, Vec2::Y);
+        // Crossing
+        assert!(distance(-x, x, -y, y) < 1e-6);
+        // Parallel, overlapping along their direction
+        assert!((distance(Vec2::ZERO, 2.0 * x, x + y, 3.0 * x + y) - 1.0).abs() < 1e-6);
+        // End to end
+        assert!((distance(Vec2::ZERO, x, 2.0 * x + y, 3.0 * x) - 2f32.sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn self_collision_gradient_matches_finite_differences() {
+        // Folded back on itself, so that the first and last links overlap without crossing
+        let ls = [0.3, 0.1, 0.3, 0.1];
+        let qs = [0.2, 1.5, 1.6, -1.0];
+        let thickness = 0.15;
+        assert!(!self_collisions(&vertices(&ls, &qs), thickness).is_empty());
+        let analytic = self_collision_gradient(&vertices(&ls, &qs), thickness);
+        let h = 1e-3;
+        for j in 0..qs.len() {
+            let (mut qs_plus, mut qs_minus) = (qs, qs);
+            qs_plus[j] += h;
+            qs_minus[j] -= h;
+            let numeric = (self_collision_penalty(&vertices(&ls, &qs_plus), thickness)
+                - self_collision_penalty(&vertices(&ls, &qs_minus), thickness))
+                / (2.0 * h);
+            assert!(
+                (analytic[j] - numeric).abs() < 1e-3,
+                "d penalty/d q[{}]: analytic {} vs numeric {}",
+                j,
+                analytic[j],
+                numeric
+            );
+        }
+    }
+}
@@ -0,0 +1,379 @@
+use bevy::prelude::*;
+use ndarray::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Rigid body simulation of a chain pinned at its origin, each link a rod of given mass and com,
+/// with gravity pulling towards -y. Integrated with fixed timestep semi-implicit (symplectic) Euler.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Dynamics {
+    /// Acceleration, in length units per s^2.
+    pub gravity: f32,
+    /// Seconds per update.
+    pub timestep: f32,
+    /// Viscous joint friction, torque per rad/s of joint velocity.
+    pub damping: f32,
+}
+
+impl Default for Dynamics {
+    fn default() -> Dynamics {
+        Dynamics {
+            gravity: 9.81,
+            timestep: 1.0 / 1000.0,
+            damping: 0.0,
+        }
+    }
+}
+
+/// Absolute link angles of relative joint angles, as a running sum. Also maps joint velocities
+/// and accelerations.
+pub fn absolute(qs: ArrayView1<f32>) -> Array1<f32> {
+    let mut cumulative = 0.0;
+    qs.mapv(|q| {
+        cumulative += q;
+        cumulative
+    })
+}
+
+/// Inverse of `absolute`.
+pub fn relative(thetas: ArrayView1<f32>) -> Array1<f32> {
+    Array1::from_shape_fn(thetas.len(), |k| {
+        if k == 0 {
+            thetas[0]
+        } else {
+            thetas[k] - thetas[k - 1]
+        }
+    })
+}
+
+/// Generalized forces on the absolute angles of torques at the joints: the torque at joint j acts
+/// on link j and reacts on link j - 1.
+pub fn joint_forces(torques: &Array1<f32>) -> Array1<f32> {
+    let n = torques.len();
+    Array1::from_shape_fn(n, |j| {
+        if j + 1 < n {
+            torques[j] - torques[j + 1]
+        } else {
+            torques[j]
+        }
+    })
+}
+
+/// Returns (a, b) where the com of link i moves by sum_k c_ik * (-sin, cos)(theta_k) * dtheta_k,
+/// c_ik being l_k for k < i and the com offset of link i for k = i; a_jk = sum_i m_i c_ij c_ik and
+/// b_j = sum_i m_i c_ij.
+fn link_sums(ls: &Array1<f32>, links: &Array1<(f32, f32)>) -> (Array2<f32>, Array1<f32>) {
+    let n = ls.len();
+    let c = |i: usize, k: usize| {
+        if k < i {
+            ls[k]
+        } else if k == i {
+            links[i].1
+        } else {
+            0.0
+        }
+    };
+    let a = Array2::from_shape_fn((n, n), |(j, k)| {
+        (j.max(k)..n).map(|i| links[i].0 * c(i, j) * c(i, k)).sum()
+    });
+    let b = Array1::from_shape_fn(n, |j| (j..n).map(|i| links[i].0 * c(i, j)).sum());
+    (a, b)
+}
+
+/// Each link has the inertia of a uniform rod about its com, m l^2 / 12.
+fn mass_matrix(
+    a: &Array2<f32>,
+    ls: &Array1<f32>,
+    links: &Array1<(f32, f32)>,
+    thetas: &Array1<f32>,
+) -> Array2<f32> {
+    let n = ls.len();
+    Array2::from_shape_fn((n, n), |(j, k)| {
+        let inertia = if j == k {
+            links[j].0 * ls[j] * ls[j] / 12.0
+        } else {
+            0.0
+        };
+        a[(j, k)] * (thetas[j] - thetas[k]).cos() + inertia
+    })
+}
+
+/// Solves m x = rhs by Gaussian elimination with partial pivoting.
+fn solve(mut m: Array2<f32>, mut rhs: Array1<f32>) -> Array1<f32> {
+    let n = rhs.len();
+    for col in 0..n {
+        let pivot = (col..n)
+            .max_by(|&i, &j| m[(i, col)].abs().partial_cmp(&m[(j, col)].abs()).unwrap())
+            .unwrap();
+        if pivot != col {
+            for k in 0..n {
+                m.swap((col, k), (pivot, k));
+            }
+            rhs.swap(col, pivot);
+        }
+        for row in (col + 1)..n {
+            let factor = m[(row, col)] / m[(col, col)];
+            for k in col..n {
+                m[(row, k)] -= factor * m[(col, k)];
+            }
+            rhs[row] -= factor * rhs[col];
+        }
+    }
+    let mut x = Array1::<f32>::zeros(n);
+    for row in (0..n).rev() {
+        let dot = ((row + 1)..n).map(|k| m[(row, k)] * x[k]).sum::<f32>();
+        x[row] = (rhs[row] - dot) / m[(row, row)];
+    }
+    x
+}
+
+/// Angular accelerations of the links from the Lagrangian equations of motion
+/// M(theta) theta'' + sum_k a_jk sin(theta_j - theta_k) theta_k'^2 + g b_j cos(theta_j) = forces_j.
+pub fn absolute_accelerations(
+    ls: &Array1<f32>,
+    links: &Array1<(f32, f32)>,
+    thetas: &Array1<f32>,
+    theta_dots: &Array1<f32>,
+    forces: &Array1<f32>,
+    gravity: f32,
+) -> Array1<f32> {
+    let n = ls.len();
+    let (a, b) = link_sums(ls, links);
+    let rhs = Array1::from_shape_fn(n, |j| {
+        let velocity_products = (0..n)
+            .map(|k| a[(j, k)] * (thetas[j] - thetas[k]).sin() * theta_dots[k] * theta_dots[k])
+            .sum::<f32>();
+        forces[j] - velocity_products - gravity * b[j] * thetas[j].cos()
+    });
+    solve(mass_matrix(&a, ls, links, thetas), rhs)
+}
+
+/// Momentum form of the equations of motion, dL/dtheta_j at theta and theta'.
+fn lagrangian_gradient(
+    a: &Array2<f32>,
+    b: &Array1<f32>,
+    thetas: &Array1<f32>,
+    theta_dots: &Array1<f32>,
+    gravity: f32,
+) -> Array1<f32> {
+    let n = thetas.len();
+    Array1::from_shape_fn(n, |j| {
+        let velocity_products = (0..n)
+            .map(|k| a[(j, k)] * (thetas[j] - thetas[k]).sin() * theta_dots[j] * theta_dots[k])
+            .sum::<f32>();
+        -velocity_products - gravity * b[j] * thetas[j].cos()
+    })
+}
+
+/// One timestep of symplectic Euler on the momenta p = M(theta) theta':
+/// p+ = p + dt (dL/dtheta(theta, M(theta)^-1 p+) + forces), theta+ = theta + dt M(theta)^-1 p+,
+/// with the implicit momentum found by fixed point iteration. Unlike updating theta' with the
+/// accelerations, keeps the energy error of a free chain bounded instead of drifting.
+/// Returns the next (thetas, theta_dots).
+pub fn absolute_step(
+    ls: &Array1<f32>,
+    links: &Array1<(f32, f32)>,
+    thetas: &Array1<f32>,
+    theta_dots: &Array1<f32>,
+    forces: &Array1<f32>,
+    dynamics: &Dynamics,
+) -> (Array1<f32>, Array1<f32>) {
+    const FIXED_POINT_ITERATIONS: usize = 4;
+    let (a, b) = link_sums(ls, links);
+    let mass_matrix_now = mass_matrix(&a, ls, links, thetas);
+    let momenta = mass_matrix_now.dot(theta_dots);
+    let mut next_theta_dots = theta_dots.clone();
+    let mut next_momenta = momenta.clone();
+    for _ in 0..FIXED_POINT_ITERATIONS {
+        next_momenta = &momenta
+            + &(dynamics.timestep
+                * (lagrangian_gradient(&a, &b, thetas, &next_theta_dots, dynamics.gravity)
+                    + forces));
+        next_theta_dots = solve(mass_matrix_now.clone(), next_momenta.clone());
+    }
+    let next_thetas = thetas + &(dynamics.timestep * &next_theta_dots);
+    let next_theta_dots = solve(mass_matrix(&a, ls, links, &next_thetas), next_momenta);
+    (next_thetas, next_theta_dots)
+}
+
+/// Puts the joints past their clamps back on them, and stops those still moving outwards with
+/// a plastic impulse: the smallest change of momentum, in the metric of the mass matrix, that
+/// zeroes their joint velocity. Unlike zeroing the joint velocities directly, this never adds
+/// energy. Returns the clamped (thetas, theta_dots).
+pub fn clamp_joints(
+    ls: &Array1<f32>,
+    links: &Array1<(f32, f32)>,
+    thetas: &Array1<f32>,
+    theta_dots: &Array1<f32>,
+    q_clamps: &Array1<(f32, f32)>,
+) -> (Array1<f32>, Array1<f32>) {
+    let n = ls.len();
+    let mut qs = relative(thetas.view());
+    let q_dots = relative(theta_dots.view());
+    let mut stopped = vec![];
+    for i in 0..n {
+        let (min, max) = q_clamps[i];
+        if (qs[i] <= min && q_dots[i] < 0.0) || (qs[i] >= max && q_dots[i] > 0.0) {
+            stopped.push(i);
+        }
+        qs[i] = qs[i].clamp(min, max);
+    }
+    let thetas = absolute(qs.view());
+    if stopped.is_empty() {
+        return (thetas, theta_dots.clone());
+    }
+    // Rows of j give the velocities of the stopped joints from the link velocities
+    let j = Array2::from_shape_fn((stopped.len(), n), |(r, k)| {
+        if k == stopped[r] {
+            1.0
+        } else if k + 1 == stopped[r] {
+            -1.0
+        } else {
+            0.0
+        }
+    });
+    let (a, _) = link_sums(ls, links);
+    let m = mass_matrix(&a, ls, links, &thetas);
+    let mut m_inv_jt = Array2::zeros((n, stopped.len()));
+    for r in 0..stopped.len() {
+        m_inv_jt
+            .column_mut(r)
+            .assign(&solve(m.clone(), j.row(r).to_owned()));
+    }
+    let impulses = solve(j.dot(&m_inv_jt), j.dot(theta_dots));
+    (thetas, theta_dots - &m_inv_jt.dot(&impulses))
+}
+
+/// Torques, counterclockwise, the joints at the vertices of a still chain must exert against
+/// gravity, the first at its origin. The end load is the (mass, com) of whatever hangs from the
+/// end, e.g. the non holding chain of a couple.
+pub fn gravity_torques(
+    vertices: &[Vec2],
+    ls: &Array1<f32>,
+    links: &Array1<(f32, f32)>,
+    end_load: (f32, Vec2),
+    gravity: f32,
+) -> Array1<f32> {
+    let n = ls.len();
+    let mut torques = Array1::zeros(n);
+    // Suffix sums of m_i * com_i.x and m_i, starting with the end load
+    let (mut weighted_com_x, mut mass) = (end_load.0 * end_load.1[0], end_load.0);
+    for j in (0..n).rev() {
+        let (link_mass, offset) = links[j];
+        weighted_com_x += link_mass * vertices[j].lerp(vertices[j + 1], offset / ls[j])[0];
+        mass += link_mass;
+        torques[j] = gravity * (weighted_com_x - mass * vertices[j][0]);
+    }
+    torques
+}
+
+/// Limit minus magnitude of each torque; negative where the joint can not exert it.
+pub fn torque_margins(torques: &Array1<f32>, torque_limits: &Array1<f32>) -> Array1<f32> {
+    assert_eq!(torques.len(), torque_limits.len());
+    torque_limits - &torques.mapv(f32::abs)
+}
+
+/// (kinetic, potential) energy, the potential measured from the height of the origin.
+pub fn energy(
+    ls: &Array1<f32>,
+    links: &Array1<(f32, f32)>,
+    thetas: &Array1<f32>,
+    theta_dots: &Array1<f32>,
+    gravity: f32,
+) -> (f32, f32) {
+    let (a, b) = link_sums(ls, links);
+    let kinetic = 0.5 * theta_dots.dot(&mass_matrix(&a, ls, links, thetas).dot(theta_dots));
+    let potential = gravity * b.dot(&thetas.mapv(f32::sin));
+    (kinetic, potential)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn total_energy(
+        ls: &Array1<f32>,
+        links: &Array1<(f32, f32)>,
+        thetas: &Array1<f32>,
+        theta_dots: &Array1<f32>,
+        gravity: f32,
+    ) -> f32 {
+        let (kinetic, potential) = energy(ls, links, thetas, theta_dots, gravity);
+        kinetic + potential
+    }
+
+    #[test]
+    fn free_swing_energy_drift_is_bounded() {
+        let dynamics = Dynamics::default();
+        let ls = arr1(&[0.4, 0.4]);
+        let links = arr1(&[(2.0, 0.15), (1.5, 0.25)]);
+        let mut thetas = arr1(&[-1.2, -1.9]);
+        let mut theta_dots = arr1(&[0.0, 0.0]);
+        let no_forces = Array1::zeros(2);
+        let initial = total_energy(&ls, &links, &thetas, &theta_dots, dynamics.gravity);
+        // Energy above hanging straight down, which the swing trades between kinetic and potential
+        let hanging = arr1(&[-std::f32::consts::FRAC_PI_2; 2]);
+        let swing = initial - total_energy(&ls, &links, &hanging, &no_forces, dynamics.gravity);
+        let steps = (20.0 / dynamics.timestep) as usize;
+        // Over the first and second half of the run
+        let mut max_drifts = [0.0f32; 2];
+        for step in 0..steps {
+            let (next_thetas, next_theta_dots) =
+                absolute_step(&ls, &links, &thetas, &theta_dots, &no_forces, &dynamics);
+            thetas = next_thetas;
+            theta_dots = next_theta_dots;
+            let drift = total_energy(&ls, &links, &thetas, &theta_dots, dynamics.gravity) - initial;
+            let half = 2 * step / steps;
+            max_drifts[half] = max_drifts[half].max(drift.abs());
+        }
+        for max_drift in &max_drifts {
+            assert!(
+                *max_drift < 1e-2 * swing,
+                "Energy drifted by {} of a swing of {}",
+                max_drift,
+                swing
+            );
+        }
+        // Bounded oscillation, not a drift that keeps growing
+        assert!(max_drifts[1] < 1.5 * max_drifts[0]);
+    }
+
+    #[test]
+    fn single_rod_matches_pendulum() {
+        // I theta'' = -m g (l / 2) cos(theta), I = m l^2 / 3 about the pivot
+        let (l, m, g, theta) = (0.5, 2.0, 9.81, 0.7f32);
+        let theta_ddots = absolute_accelerations(
+            &arr1(&[l]),
+            &arr1(&[(m, l / 2.0)]),
+            &arr1(&[theta]),
+            &arr1(&[0.0]),
+            &arr1(&[0.0]),
+            g,
+        );
+        let expected = -m * g * (l / 2.0) * theta.cos() / (m * l * l / 3.0);
+        assert!((theta_ddots[0] - expected).abs() < 1e-4);
+    }
+
+    #[test]
+    fn gravity_torques_hold_the_chain_still() {
+        let (gravity, origin) = (9.81, Vec2::new(0.1, -0.2));
+        let ls = arr1(&[0.3, 0.25, 0.2]);
+        let links = arr1(&[(40.0, 0.2), (3.0, 0.1), (1.5, 0.12)]);
+        let qs = arr1(&[-0.4, -1.0, 0.6]);
+        let mut vertices = vec![origin];
+        for (l, theta) in ls.iter().zip(absolute(qs.view()).iter()) {
+            let last = *vertices.last().unwrap();
+            vertices.push(last + *l * Vec2::new(theta.cos(), theta.sin()));
+        }
+        let torques = gravity_torques(&vertices, &ls, &links, (0.0, Vec2::ZERO), gravity);
+        let theta_ddots = absolute_accelerations(
+            &ls,
+            &links,
+            &absolute(qs.view()),
+            &Array1::zeros(3),
+            &joint_forces(&torques),
+            gravity,
+        );
+        assert!(theta_ddots.iter().all(|theta_ddot| theta_ddot.abs() < 1e-3));
+    }
+}
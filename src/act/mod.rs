@@ -1,2 +1,4 @@
+pub mod collision;
+pub mod dynamics;
 pub mod one_holding_switchable_nr_couple;
 pub mod switchable_nr;
@@ -1,3 +1,5 @@
+use super::collision::*;
+use super::dynamics::*;
 use super::switchable_nr::*;
 
 use bevy::prelude::*;
@@ -17,15 +19,21 @@ impl OneHoldingSwitchableNRCouple {
         ls_holding: &[f32],
         qs_holding: &[f32],
         q_clamps_holding: &[(f32, f32)],
+        links_holding: Option<&[(f32, f32)]>,
+        joint_limits_holding: Option<JointLimits>,
         ls_non_holding: &[f32],
         qs_non_holding: &[f32],
         q_clamps_non_holding: &[(f32, f32)],
+        links_non_holding: Option<&[(f32, f32)]>,
+        joint_limits_non_holding: Option<JointLimits>,
     ) -> Self {
         let holding = SwitchableNR::new(
             origin_holding,
             ls_holding,
             qs_holding,
             q_clamps_holding,
+            links_holding,
+            joint_limits_holding,
             holding_side.clone(),
         );
         let origin_non_holding = holding.get_last_vertex();
@@ -34,6 +42,8 @@ impl OneHoldingSwitchableNRCouple {
             ls_non_holding,
             qs_non_holding,
             q_clamps_non_holding,
+            links_non_holding,
+            joint_limits_non_holding,
             holding_side.clone(),
         );
         OneHoldingSwitchableNRCouple {
@@ -76,6 +86,64 @@ impl OneHoldingSwitchableNRCouple {
         (m1 * com1 + m2 * com2) / (m1 + m2)
     }
 
+    /// Vertices of both chains as one, from the holding origin to the non holding end.
+    pub fn get_all_vertices(&self) -> Vec<Vec2> {
+        let mut vertices = self.holding.get_all_vertices();
+        vertices.extend(self.non_holding.get_all_vertices().into_iter().skip(1));
+        vertices
+    }
+
+    /// (i, k, clearance) of the colliding links, indexed along get_all_vertices, within and
+    /// across the chains.
+    pub fn self_collisions(&self, thickness: f32) -> Vec<(usize, usize, f32)> {
+        self_collisions(&self.get_all_vertices(), thickness)
+    }
+
+    /// Gradients of the self collision penalty w.r.t. the (holding, non holding) qs. The non
+    /// holding q0 is absolute, so a holding joint moves the non holding chain without rotating it.
+    pub fn self_collision_gradients(
+        &self,
+        self_collision: &SelfCollision,
+    ) -> (Array1<f32>, Array1<f32>) {
+        let holding_n = self.holding.get_current_state().0;
+        let gradient = self_collision.gradient(&self.get_all_vertices());
+        let holding_gradient = gradient
+            .slice(s![..holding_n])
+            .mapv(|g| g - gradient[holding_n]);
+        (holding_gradient, gradient.slice(s![holding_n..]).to_owned())
+    }
+
+    /// Static analysis of the current pose: the torques the joints of the holding chain, the
+    /// first at the hold, must exert to keep the couple still under gravity.
+    pub fn holding_gravity_torques(&self, gravity: f32) -> Array1<f32> {
+        let (_, _, ls, _, _, _) = self.holding.get_current_state();
+        gravity_torques(
+            &self.holding.get_all_vertices(),
+            ls,
+            self.holding.get_links(),
+            (
+                self.non_holding.get_total_mass(),
+                self.non_holding.get_center_of_mass(),
+            ),
+            gravity,
+        )
+    }
+
+    /// (mass, com offset from the end of the holding chain) of the non holding chain, i.e. the end
+    /// load of the holding chain for `TorqueLimits`.
+    pub fn holding_end_load(&self) -> (f32, Vec2) {
+        (
+            self.non_holding.get_total_mass(),
+            self.non_holding.get_center_of_mass() - self.holding.get_last_vertex(),
+        )
+    }
+
+    /// Margins of the holding gravity torques to the given limits per holding joint; the pose
+    /// can be held iff none is negative.
+    pub fn holding_torque_margins(&self, torque_limits: &Array1<f32>, gravity: f32) -> Array1<f32> {
+        torque_margins(&self.holding_gravity_torques(gravity), torque_limits)
+    }
+
     pub fn update(&mut self, holding_delta_qs: Array1<f32>, non_holding_delta_qs: Array1<f32>) {
         self.holding.update(holding_delta_qs);
         let origin_non_holding = self.holding.get_last_vertex();
@@ -83,6 +151,68 @@ impl OneHoldingSwitchableNRCouple {
         self.non_holding.update(non_holding_delta_qs);
     }
 
+    /// Dynamic alternative to update, simulating both chains as one, pinned at the holding origin.
+    /// The first non holding torque acts between the last holding link and the first non holding
+    /// one.
+    pub fn update_with_torques(
+        &mut self,
+        holding_torques: &Array1<f32>,
+        non_holding_torques: &Array1<f32>,
+        dynamics: &Dynamics,
+    ) {
+        let (holding_n, _, holding_ls, holding_qs, holding_q_clamps, _) =
+            self.holding.get_current_state();
+        let (non_holding_n, _, non_holding_ls, non_holding_qs, non_holding_q_clamps, _) =
+            self.non_holding.get_current_state();
+        assert_eq!(holding_torques.len(), holding_n);
+        assert_eq!(non_holding_torques.len(), non_holding_n);
+        // Both qs start with an absolute angle, so the absolute angles of the joint chain are
+        // just those of each chain, one after the other
+        let concatenate = |a: Array1<f32>, b: Array1<f32>| {
+            a.iter().chain(b.iter()).copied().collect::<Array1<f32>>()
+        };
+        let ls = concatenate(holding_ls.clone(), non_holding_ls.clone());
+        let links = self
+            .holding
+            .get_links()
+            .iter()
+            .chain(self.non_holding.get_links().iter())
+            .copied()
+            .collect::<Array1<(f32, f32)>>();
+        let thetas = concatenate(absolute(holding_qs.view()), absolute(non_holding_qs.view()));
+        let theta_dots = concatenate(
+            absolute(self.holding.get_q_dots().view()),
+            absolute(self.non_holding.get_q_dots().view()),
+        );
+        let torques = concatenate(holding_torques.clone(), non_holding_torques.clone())
+            - dynamics.damping * relative(theta_dots.view());
+        let (thetas, theta_dots) = absolute_step(
+            &ls,
+            &links,
+            &thetas,
+            &theta_dots,
+            &joint_forces(&torques),
+            dynamics,
+        );
+        // The first non holding clamp is infinite, so clamping the joint chain is clamping each
+        let q_clamps = holding_q_clamps
+            .iter()
+            .chain(non_holding_q_clamps.iter())
+            .copied()
+            .collect::<Array1<(f32, f32)>>();
+        let (thetas, theta_dots) = clamp_joints(&ls, &links, &thetas, &theta_dots, &q_clamps);
+        self.holding.set_dynamic_state(
+            relative(thetas.slice(s![..holding_n])),
+            relative(theta_dots.slice(s![..holding_n])),
+        );
+        let origin_non_holding = self.holding.get_last_vertex();
+        self.non_holding.set_origin(origin_non_holding);
+        self.non_holding.set_dynamic_state(
+            relative(thetas.slice(s![holding_n..])),
+            relative(theta_dots.slice(s![holding_n..])),
+        );
+    }
+
     pub fn switch_hold(&mut self) {
         // Switch pivot
         self.non_holding.switch_pivot();
@@ -94,3 +224,68 @@ impl OneHoldingSwitchableNRCouple {
         self.is_holding_as_initialized = !self.is_holding_as_initialized;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn couple(holding_qs: &[f32], non_holding_qs: &[f32]) -> OneHoldingSwitchableNRCouple {
+        let (inf, pi) = (f32::INFINITY, std::f32::consts::PI);
+        OneHoldingSwitchableNRCouple::new(
+            &Side::Left,
+            Vec2::new(0.1, 0.2),
+            &[0.3, 0.2],
+            holding_qs,
+            &[(-inf, inf), (-pi, pi)],
+            None,
+            None,
+            &[0.2, 0.3],
+            non_holding_qs,
+            &[(-inf, inf), (-pi, pi)],
+            None,
+            None,
+        )
+    }
+
+    #[test]
+    fn self_collision_gradients_match_finite_differences() {
+        // The last non holding link folds back onto both holding links, without crossing them
+        let (holding_qs, non_holding_qs) = ([-0.8, 0.7], [-1.0, -2.6]);
+        let self_collision = SelfCollision {
+            thickness: 0.1,
+            weight: 2.0,
+        };
+        let penalty = |holding_qs: &[f32], non_holding_qs: &[f32]| {
+            self_collision.penalty(&couple(holding_qs, non_holding_qs).get_all_vertices())
+        };
+        assert!(!couple(&holding_qs, &non_holding_qs)
+            .self_collisions(self_collision.thickness)
+            .is_empty());
+        let (holding_gradient, non_holding_gradient) =
+            couple(&holding_qs, &non_holding_qs).self_collision_gradients(&self_collision);
+        let h = 1e-3;
+        for j in 0..4 {
+            let (mut holding_plus, mut non_holding_plus) = (holding_qs, non_holding_qs);
+            let (mut holding_minus, mut non_holding_minus) = (holding_qs, non_holding_qs);
+            let analytic = if j < 2 {
+                holding_plus[j] += h;
+                holding_minus[j] -= h;
+                holding_gradient[j]
+            } else {
+                non_holding_plus[j - 2] += h;
+                non_holding_minus[j - 2] -= h;
+                non_holding_gradient[j - 2]
+            };
+            let numeric = (penalty(&holding_plus, &non_holding_plus)
+                - penalty(&holding_minus, &non_holding_minus))
+                / (2.0 * h);
+            assert!(
+                (analytic - numeric).abs() < 1e-3,
+                "d penalty/d q[{}]: analytic {} vs numeric {}",
+                j,
+                analytic,
+                numeric
+            );
+        }
+    }
+}
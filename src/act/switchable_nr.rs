@@ -1,13 +1,73 @@
+use super::dynamics::*;
 use bevy::prelude::*;
 use ndarray::prelude::*;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub enum Side {
     Left,
     Right,
 }
 
+/// Per joint limits of the kinematic update, the first joint at the pivot.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct JointLimits {
+    /// Largest |delta q| per tick.
+    pub velocities: Vec<f32>,
+    /// Largest change of delta q from one tick to the next.
+    #[serde(default)]
+    pub accelerations: Option<Vec<f32>>,
+    /// Scale all the delta qs (or their changes) by one factor, which keeps the direction of the
+    /// commanded motion, instead of clamping each joint on its own.
+    #[serde(default)]
+    pub uniform_scaling: bool,
+}
+
+impl JointLimits {
+    /// Each joint clamped to `SwitchableNR::DEFAULT_MAX_DELTA_Q`, without acceleration limits.
+    pub fn default_for(n: usize) -> JointLimits {
+        JointLimits {
+            velocities: vec![SwitchableNR::DEFAULT_MAX_DELTA_Q; n],
+            accelerations: None,
+            uniform_scaling: false,
+        }
+    }
+
+    /// The limits of the same joints once the chain is pivoted at its other end.
+    fn reversed(&self) -> JointLimits {
+        let reverse = |limits: &[f32]| {
+            let mut reversed = vec![limits[0]];
+            reversed.extend(limits.iter().skip(1).rev());
+            reversed
+        };
+        JointLimits {
+            velocities: reverse(&self.velocities),
+            accelerations: self.accelerations.as_deref().map(reverse),
+            uniform_scaling: self.uniform_scaling,
+        }
+    }
+}
+
+/// Brings every value within [-limit, limit], by clamping each or by scaling all of them by the
+/// largest factor that brings in the worst one.
+fn limit(values: Array1<f32>, limits: &[f32], uniform_scaling: bool) -> Array1<f32> {
+    if uniform_scaling {
+        let scale = values
+            .iter()
+            .zip(limits.iter())
+            .map(|(value, limit)| limit / value.abs())
+            .fold(1f32, f32::min);
+        scale * values
+    } else {
+        let mut values = values;
+        for (value, limit) in values.iter_mut().zip(limits.iter()) {
+            *value = value.clamp(-limit, *limit);
+        }
+        values
+    }
+}
+
 #[derive(Clone)]
 pub struct SwitchableNR {
     // State
@@ -16,18 +76,27 @@ pub struct SwitchableNR {
     ls: Array1<f32>,
     qs: Array1<f32>,
     q_clamps: Array1<(f32, f32)>,
+    // (mass, com offset from the link's first vertex along it) per link
+    links: Array1<(f32, f32)>,
+    joint_limits: JointLimits,
     pivoting_side: Side,
+    // Only moved by update_with_torques
+    q_dots: Array1<f32>,
+    // Only moved by update
+    last_delta_qs: Array1<f32>,
 }
 
 impl SwitchableNR {
     pub const GOAL_REACHED_SLACK: f32 = 5.0;
-    const MAX_DELTA_Q: f32 = 0.01;
+    pub const DEFAULT_MAX_DELTA_Q: f32 = 0.01;
 
     pub fn new(
         origin: Vec2,
         ls: &[f32],
         qs: &[f32],
         q_clamps: &[(f32, f32)],
+        links: Option<&[(f32, f32)]>,
+        joint_limits: Option<JointLimits>,
         pivoting_side: Side,
     ) -> Self {
         assert!(ls.len() > 0, "Zero links argument.");
@@ -54,13 +123,54 @@ impl SwitchableNR {
             q_clamps[0] == (-f32::INFINITY, f32::INFINITY),
             "First q clamp has to be (-inf, inf)."
         );
+        let links = match links {
+            Some(links) => links.to_vec(),
+            None => length_weighted_links(ls),
+        };
+        assert_eq!(
+            ls.len(),
+            links.len(),
+            "Unequal number of lengths and links arguments."
+        );
+        for i in 0..ls.len() {
+            assert!(links[i].0 > 0.0, "Non-positive mass argument.");
+            assert!(
+                0.0 <= links[i].1 && links[i].1 <= ls[i],
+                "Com offset argument off its link."
+            );
+        }
+        let joint_limits = joint_limits.unwrap_or_else(|| JointLimits::default_for(ls.len()));
+        assert_eq!(
+            ls.len(),
+            joint_limits.velocities.len(),
+            "Unequal number of lengths and velocity limits arguments."
+        );
+        assert!(
+            joint_limits.velocities.iter().all(|limit| *limit > 0.0),
+            "Non-positive velocity limit argument."
+        );
+        if let Some(accelerations) = &joint_limits.accelerations {
+            assert_eq!(
+                ls.len(),
+                accelerations.len(),
+                "Unequal number of lengths and acceleration limits arguments."
+            );
+            assert!(
+                accelerations.iter().all(|limit| *limit > 0.0),
+                "Non-positive acceleration limit argument."
+            );
+        }
         SwitchableNR {
             n: ls.len(),
             origin,
             ls: arr1(ls),
             qs: arr1(qs),
             q_clamps: arr1(q_clamps),
+            links: arr1(&links),
+            joint_limits,
             pivoting_side,
+            q_dots: Array1::zeros(ls.len()),
+            last_delta_qs: Array1::zeros(ls.len()),
         }
     }
 
@@ -92,11 +202,24 @@ impl SwitchableNR {
         let mut q_clamps = vec![(-f32::INFINITY, f32::INFINITY)];
         q_clamps.append(&mut last_n_1_q_clamps);
         self.q_clamps = arr1(&q_clamps);
+        // links, with offsets now from the other end
+        self.links = self
+            .links
+            .iter()
+            .zip(self.ls.iter().rev())
+            .rev()
+            .map(|(&(mass, offset), l)| (mass, l - offset))
+            .collect();
+        // joint_limits
+        self.joint_limits = self.joint_limits.reversed();
         // pivoting_side
         self.pivoting_side = match self.pivoting_side {
             Side::Left => Side::Right,
             Side::Right => Side::Left,
         };
+        // Grabbing the new pivot stops the chain
+        self.q_dots = Array1::zeros(self.n);
+        self.last_delta_qs = Array1::zeros(self.n);
     }
 
     pub fn get_current_state(
@@ -119,23 +242,35 @@ impl SwitchableNR {
         )
     }
 
+    pub fn get_links(&self) -> &Array1<(f32, f32)> {
+        &self.links
+    }
+
+    pub fn get_joint_limits(&self) -> &JointLimits {
+        &self.joint_limits
+    }
+
     pub fn set_origin(&mut self, origin: Vec2) {
         self.origin = origin;
     }
 
+    /// Moves by the delta qs within the joint limits, the acceleration limits relative to the
+    /// motion of the previous update.
     pub fn update(&mut self, control_delta_qs: Array1<f32>) {
         assert_eq!(control_delta_qs.len(), self.n);
-        let control_delta_qs = {
-            let mut control_delta_qs = control_delta_qs;
-            control_delta_qs.mapv_inplace(|delta_q| {
-                if delta_q.abs() > Self::MAX_DELTA_Q {
-                    Self::MAX_DELTA_Q * delta_q.signum()
-                } else {
-                    delta_q
-                }
-            });
-            control_delta_qs
-        };
+        let JointLimits {
+            velocities,
+            accelerations,
+            uniform_scaling,
+        } = &self.joint_limits;
+        let mut control_delta_qs = limit(control_delta_qs, velocities, *uniform_scaling);
+        if let Some(accelerations) = accelerations {
+            // Both within the velocity limits, so anything between them is too
+            let change = control_delta_qs - &self.last_delta_qs;
+            control_delta_qs =
+                &self.last_delta_qs + &limit(change, accelerations, *uniform_scaling);
+        }
+        let previous_qs = self.qs.clone();
         self.qs += &control_delta_qs;
         for i in 0..self.n {
             let (min, max) = self.q_clamps[i];
@@ -145,6 +280,35 @@ impl SwitchableNR {
                 self.qs[i] = max
             }
         }
+        self.last_delta_qs = &self.qs - &previous_qs;
+    }
+
+    pub fn get_q_dots(&self) -> &Array1<f32> {
+        &self.q_dots
+    }
+
+    /// Dynamic alternative to update: advances one timestep of the equations of motion of the
+    /// chain pinned at its origin, under the given joint torques.
+    pub fn update_with_torques(&mut self, torques: &Array1<f32>, dynamics: &Dynamics) {
+        assert_eq!(torques.len(), self.n);
+        let torques = torques - &(dynamics.damping * &self.q_dots);
+        let (thetas, theta_dots) = absolute_step(
+            &self.ls,
+            &self.links,
+            &absolute(self.qs.view()),
+            &absolute(self.q_dots.view()),
+            &joint_forces(&torques),
+            dynamics,
+        );
+        let (thetas, theta_dots) =
+            clamp_joints(&self.ls, &self.links, &thetas, &theta_dots, &self.q_clamps);
+        self.set_dynamic_state(relative(thetas.view()), relative(theta_dots.view()));
+    }
+
+    /// Expects qs within their clamps.
+    pub(super) fn set_dynamic_state(&mut self, qs: Array1<f32>, q_dots: Array1<f32>) {
+        self.qs = qs;
+        self.q_dots = q_dots;
     }
 
     pub fn get_last_vertex(&self) -> Vec2 {
@@ -175,7 +339,7 @@ impl SwitchableNR {
     }
 
     pub fn get_total_mass(&self) -> f32 {
-        self.ls.sum()
+        self.links.iter().map(|(mass, _)| mass).sum()
     }
 
     pub fn get_center_of_mass(&self) -> Vec2 {
@@ -186,10 +350,11 @@ impl SwitchableNR {
             cumulative_rotation += self.qs[i];
             let e2 =
                 e1 + Vec2::new(cumulative_rotation.cos(), cumulative_rotation.sin()) * self.ls[i];
-            com += self.ls[i] * (e1 + e2) / 2.0;
+            let (mass, offset) = self.links[i];
+            com += mass * e1.lerp(e2, offset / self.ls[i]);
             e1 = e2;
         }
-        com / self.ls.sum()
+        com / self.get_total_mass()
     }
 
     pub fn pose_to_transforms(&self) -> Vec<(Vec2, f32)> {
@@ -204,3 +369,131 @@ impl SwitchableNR {
         transforms
     }
 }
+
+/// Links weighted by their lengths, with their com at their middle, as uniform rods of equal
+/// density.
+pub fn length_weighted_links(ls: &[f32]) -> Vec<(f32, f32)> {
+    ls.iter().map(|&l| (l, l / 2.0)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain(joint_limits: JointLimits) -> SwitchableNR {
+        let inf = f32::INFINITY;
+        SwitchableNR::new(
+            Vec2::new(0.0, 0.0),
+            &[0.2, 0.3, 0.1, 0.2],
+            &[0.4, -1.0, 0.5, -0.3],
+            &[(-inf, inf), (-3.0, 3.0), (-3.0, 3.0), (-3.0, 3.0)],
+            None,
+            Some(joint_limits),
+            Side::Left,
+        )
+    }
+
+    /// The delta qs an update with control delta qs actually moves by.
+    fn moved(agent: &mut SwitchableNR, control_delta_qs: Array1<f32>) -> Array1<f32> {
+        let qs = agent.get_current_state().3.clone();
+        agent.update(control_delta_qs);
+        agent.get_current_state().3 - &qs
+    }
+
+    fn assert_close(a: &Array1<f32>, b: &Array1<f32>) {
+        for (x, y) in a.iter().zip(b) {
+            assert!((x - y).abs() < 1e-6, "{} vs {}", a, b);
+        }
+    }
+
+    #[test]
+    fn clamps_each_joint_without_scaling() {
+        let mut agent = chain(JointLimits {
+            velocities: vec![0.1, 0.2, 0.3, 0.4],
+            accelerations: None,
+            uniform_scaling: false,
+        });
+        let delta_qs = moved(&mut agent, array![0.5, -0.05, -1.0, 0.4]);
+        assert_close(&delta_qs, &array![0.1, -0.05, -0.3, 0.4]);
+    }
+
+    #[test]
+    fn uniform_scaling_keeps_direction_within_limits() {
+        let velocities = vec![0.1, 0.2, 0.3, 0.4];
+        let mut agent = chain(JointLimits {
+            velocities: velocities.clone(),
+            accelerations: None,
+            uniform_scaling: true,
+        });
+        let control_delta_qs = array![0.5, -0.05, -1.0, 0.0];
+        let delta_qs = moved(&mut agent, control_delta_qs.clone());
+        // The first joint is the binding one
+        assert_close(&delta_qs, &(0.2 * &control_delta_qs));
+        for (delta_q, limit) in delta_qs.iter().zip(&velocities) {
+            assert!(delta_q.abs() <= limit + 1e-6);
+        }
+        // Within limits already, so untouched
+        let control_delta_qs = array![0.05, -0.1, 0.2, 0.0];
+        assert_close(
+            &moved(&mut agent, control_delta_qs.clone()),
+            &control_delta_qs,
+        );
+    }
+
+    #[test]
+    fn acceleration_limits_bound_the_change_of_delta_qs() {
+        for uniform_scaling in [false, true] {
+            let accelerations = vec![0.01, 0.02, 0.03, 0.04];
+            let mut agent = chain(JointLimits {
+                velocities: vec![1.0; 4],
+                accelerations: Some(accelerations.clone()),
+                uniform_scaling,
+            });
+            let mut last_delta_qs = Array1::<f32>::zeros(4);
+            for control_delta_qs in [
+                array![0.5, -0.5, 0.5, -0.5],
+                array![0.5, -0.5, 0.5, -0.5],
+                array![-0.5, 0.5, 0.0, 0.5],
+                array![0.0, 0.0, 0.0, 0.0],
+            ] {
+                let delta_qs = moved(&mut agent, control_delta_qs);
+                for i in 0..4 {
+                    assert!((delta_qs[i] - last_delta_qs[i]).abs() <= accelerations[i] + 1e-6);
+                }
+                last_delta_qs = delta_qs;
+            }
+        }
+    }
+
+    #[test]
+    fn reversed_limits_follow_switch_pivot() {
+        let joint_limits = JointLimits {
+            velocities: vec![0.1, 0.2, 0.3, 0.4],
+            accelerations: Some(vec![1.0, 2.0, 3.0, 4.0]),
+            uniform_scaling: false,
+        };
+        let mut agent = chain(joint_limits.clone());
+        let qs = agent.get_current_state().3.clone();
+        agent.switch_pivot();
+        let switched_qs = agent.get_current_state().3.clone();
+        let switched_limits = agent.get_joint_limits().clone();
+        // Joint j of the switched chain is joint n - j of the original one
+        for j in 1..4 {
+            assert_eq!(switched_qs[j], -qs[4 - j]);
+            assert_eq!(
+                switched_limits.velocities[j],
+                joint_limits.velocities[4 - j]
+            );
+            assert_eq!(
+                switched_limits.accelerations.as_ref().unwrap()[j],
+                joint_limits.accelerations.as_ref().unwrap()[4 - j]
+            );
+        }
+        agent.switch_pivot();
+        assert_eq!(agent.get_joint_limits().velocities, joint_limits.velocities);
+        assert_eq!(
+            agent.get_joint_limits().accelerations,
+            joint_limits.accelerations
+        );
+    }
+}
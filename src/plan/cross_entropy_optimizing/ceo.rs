@@ -1,4 +1,7 @@
-use super::fcn::*;
+use super::fcn::FCN;
+use super::policy::{BoundPolicy, Policy};
+use super::telemetry::{GenerationRecord, Observer};
+use crate::plan::{derive_seed, seeded_rng};
 use ndarray::{prelude::*, stack};
 use ndarray_rand::{
     rand_distr::{NormalError, StandardNormal},
@@ -6,15 +9,88 @@ use ndarray_rand::{
 };
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::{fs, fs::File, io, io::BufReader, path::PathBuf, time::Instant};
 
-pub trait Reward {
+/// Reward of a policy with the given params, episode by episode.
+pub trait Reward<P = FCN> {
+    fn episode_reward(
+        &self,
+        policy: &dyn BoundPolicy,
+        num_episode_ticks: usize,
+        episode_seed: u64,
+    ) -> f32;
+
+    /// Mean of the episode rewards, the episodes seeded with derive_seed(seed, episode).
+    /// The params are bound once for all the episodes.
     fn average_reward(
         &self,
-        fcn: &FCN,
+        policy: &P,
         params: &Array1<f32>,
         num_episodes: usize,
         num_episode_ticks: usize,
-    ) -> f32;
+        seed: u64,
+    ) -> f32
+    where
+        P: Policy,
+    {
+        let policy = policy.bind(params);
+        let cumulative_reward = (0..num_episodes)
+            .map(|episode| {
+                self.episode_reward(
+                    policy.as_ref(),
+                    num_episode_ticks,
+                    derive_seed(seed, episode as u64),
+                )
+            })
+            .sum::<f32>();
+        cumulative_reward / num_episodes as f32
+    }
+
+    /// Same as average_reward, running the episodes in parallel. The episode rewards are still
+    /// summed in order, so the result is identical.
+    fn par_average_reward(
+        &self,
+        policy: &P,
+        params: &Array1<f32>,
+        num_episodes: usize,
+        num_episode_ticks: usize,
+        seed: u64,
+    ) -> f32
+    where
+        Self: Sync,
+        P: Policy,
+    {
+        let policy = policy.bind(params);
+        let episode_rewards = (0..num_episodes)
+            .into_par_iter()
+            .map(|episode| {
+                self.episode_reward(
+                    policy.as_ref(),
+                    num_episode_ticks,
+                    derive_seed(seed, episode as u64),
+                )
+            })
+            .collect::<Vec<f32>>();
+        episode_rewards.iter().sum::<f32>() / num_episodes as f32
+    }
+}
+
+/// Reward::average_reward, or Reward::par_average_reward if parallel_episodes, as evaluated by
+/// the optimizers.
+pub(super) fn average_reward<P: Policy>(
+    reward: &(dyn Reward<P> + std::marker::Sync),
+    policy: &P,
+    params: &Array1<f32>,
+    num_episodes: usize,
+    num_episode_ticks: usize,
+    parallel_episodes: bool,
+    seed: u64,
+) -> f32 {
+    if parallel_episodes {
+        reward.par_average_reward(policy, params, num_episodes, num_episode_ticks, seed)
+    } else {
+        reward.average_reward(policy, params, num_episodes, num_episode_ticks, seed)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -27,6 +103,18 @@ pub struct CEO {
     pub elite_frac: f32,
     pub initial_std: f32,
     pub noise_factor: f32,
+    #[serde(default)]
+    pub seed: u64,
+    #[serde(default)]
+    pub stopping: Stopping,
+    /// By default all candidates of a generation, and the current params, are evaluated on the
+    /// same episodes (common random numbers), so that elites are not selected on luckier
+    /// episodes. If set, each candidate draws its own episodes instead.
+    #[serde(default)]
+    pub independent_episodes: bool,
+    /// Run the episodes of each evaluation in parallel too, not just the candidates.
+    #[serde(default)]
+    pub parallel_episodes: bool,
 }
 
 impl Default for CEO {
@@ -39,35 +127,245 @@ impl Default for CEO {
             elite_frac: 0.25,
             initial_std: 2.0,
             noise_factor: 2.0,
+            seed: 0,
+            stopping: Stopping::default(),
+            independent_episodes: false,
+            parallel_episodes: false,
+        }
+    }
+}
+
+/// Criteria, checked after every generation, to stop before running all generations.
+/// An unset criterion never triggers.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Stopping {
+    /// (window, tolerance): the mean reward varied by at most tolerance over the last window
+    /// generations.
+    pub plateau: Option<(usize, f32)>,
+    /// The noise std of every param fell below this.
+    pub min_noise_std: Option<f32>,
+    /// Seconds since the start of the (possibly resumed) run.
+    pub max_wall_time: Option<f32>,
+    /// The reward of the current params reached this.
+    pub target_reward: Option<f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum StopReason {
+    Generations,
+    Plateau,
+    NoiseCollapse,
+    WallTime,
+    TargetReward,
+    Observer,
+}
+
+impl Stopping {
+    pub(super) fn check(
+        &self,
+        mean_rewards: &[f32],
+        record: &GenerationRecord,
+    ) -> Option<StopReason> {
+        if let Some((window, tolerance)) = self.plateau {
+            if window > 0 && mean_rewards.len() >= window {
+                let window = &mean_rewards[mean_rewards.len() - window..];
+                let max = window.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+                let min = window.iter().cloned().fold(f32::INFINITY, f32::min);
+                if max - min <= tolerance {
+                    return Some(StopReason::Plateau);
+                }
+            }
+        }
+        if let Some(min_noise_std) = self.min_noise_std {
+            if record.noise_std_max < min_noise_std {
+                return Some(StopReason::NoiseCollapse);
+            }
+        }
+        if let Some(max_wall_time) = self.max_wall_time {
+            if record.wall_time >= max_wall_time {
+                return Some(StopReason::WallTime);
+            }
         }
+        if let Some(target_reward) = self.target_reward {
+            if record.current_params_reward >= target_reward {
+                return Some(StopReason::TargetReward);
+            }
+        }
+        None
     }
 }
 
+/// Everything needed to continue an optimization run from the start of `generation`.
+/// The rng state is fully determined by `seed` and `generation`, so a resumed run
+/// is identical to an uninterrupted one, as long as it has the same configuration, which is
+/// recorded alongside.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Checkpoint {
+    pub generation: usize,
+    pub seed: u64,
+    pub batch_size: usize,
+    pub elite_frac: f32,
+    pub num_episodes: usize,
+    pub num_episode_ticks: usize,
+    pub noise_factor: f32,
+    pub independent_episodes: bool,
+    pub parallel_episodes: bool,
+    pub params: Array1<f32>,
+    pub noise_std: Array1<f32>,
+    /// Mean reward of each completed generation.
+    pub mean_rewards: Vec<f32>,
+}
+
+impl Checkpoint {
+    pub fn load(path: &PathBuf) -> io::Result<Checkpoint> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    /// Writes to a sibling temporary file first, so that a crash mid write keeps the previous
+    /// checkpoint.
+    pub fn save(&self, path: &PathBuf) -> io::Result<()> {
+        let tmp_path = path.with_extension("tmp");
+        serde_json::to_writer(&File::create(&tmp_path)?, self)?;
+        fs::rename(tmp_path, path)
+    }
+}
+
+/// Writes a checkpoint to `path` after every `every` generations and after the last one.
+pub struct Checkpointing {
+    pub path: PathBuf,
+    pub every: usize,
+}
+
 impl CEO {
-    pub fn optimize(
+    pub fn optimize<P: Policy>(
         &self,
-        fcn: &mut FCN,
-        reward: &(dyn Reward + std::marker::Sync),
-    ) -> Result<(f32, Array1<f32>), NormalError> {
+        policy: &mut P,
+        reward: &(dyn Reward<P> + std::marker::Sync),
+        observer: &mut dyn Observer,
+    ) -> Result<(f32, Array1<f32>, StopReason), NormalError> {
+        let checkpoint = self.initial_checkpoint(policy);
+        self.run(policy, reward, observer, checkpoint, None)
+    }
+
+    pub fn optimize_with_checkpoints<P: Policy>(
+        &self,
+        policy: &mut P,
+        reward: &(dyn Reward<P> + std::marker::Sync),
+        observer: &mut dyn Observer,
+        checkpointing: &Checkpointing,
+    ) -> Result<(f32, Array1<f32>, StopReason), NormalError> {
+        let checkpoint = self.initial_checkpoint(policy);
+        self.run(policy, reward, observer, checkpoint, Some(checkpointing))
+    }
+
+    /// Continues a run of this same configuration from a checkpoint, overwriting the params of policy.
+    pub fn resume<P: Policy>(
+        &self,
+        policy: &mut P,
+        reward: &(dyn Reward<P> + std::marker::Sync),
+        observer: &mut dyn Observer,
+        checkpoint: Checkpoint,
+        checkpointing: Option<&Checkpointing>,
+    ) -> Result<(f32, Array1<f32>, StopReason), NormalError> {
+        assert_eq!(
+            checkpoint.seed, self.seed,
+            "Checkpoint was written by a run with a different seed."
+        );
+        assert!(
+            checkpoint.batch_size == self.batch_size
+                && checkpoint.elite_frac == self.elite_frac
+                && checkpoint.num_episodes == self.num_episodes
+                && checkpoint.num_episode_ticks == self.num_episode_ticks
+                && checkpoint.noise_factor == self.noise_factor
+                && checkpoint.independent_episodes == self.independent_episodes
+                && checkpoint.parallel_episodes == self.parallel_episodes,
+            "Checkpoint was written by a run with a different configuration."
+        );
+        assert_eq!(
+            checkpoint.params.len(),
+            policy.params().len(),
+            "Invalid params len for checkpoint."
+        );
+        assert_eq!(
+            checkpoint.noise_std.len(),
+            policy.params().len(),
+            "Invalid noise std len for checkpoint."
+        );
+        assert!(
+            checkpoint.generation <= self.generations,
+            "Checkpoint is past the last generation."
+        );
+        policy.set_params(checkpoint.params.clone());
+        self.run(policy, reward, observer, checkpoint, checkpointing)
+    }
+
+    fn initial_checkpoint<P: Policy>(&self, policy: &P) -> Checkpoint {
+        Checkpoint {
+            generation: 0,
+            seed: self.seed,
+            batch_size: self.batch_size,
+            elite_frac: self.elite_frac,
+            num_episodes: self.num_episodes,
+            num_episode_ticks: self.num_episode_ticks,
+            noise_factor: self.noise_factor,
+            independent_episodes: self.independent_episodes,
+            parallel_episodes: self.parallel_episodes,
+            params: policy.params().clone(),
+            noise_std: Array::from_elem((policy.params().len(),), self.initial_std),
+            mean_rewards: vec![],
+        }
+    }
+
+    fn run<P: Policy>(
+        &self,
+        policy: &mut P,
+        reward: &(dyn Reward<P> + std::marker::Sync),
+        observer: &mut dyn Observer,
+        mut checkpoint: Checkpoint,
+        checkpointing: Option<&Checkpointing>,
+    ) -> Result<(f32, Array1<f32>, StopReason), NormalError> {
+        if let Some(checkpointing) = checkpointing {
+            assert!(
+                checkpointing.every > 0,
+                "Checkpoint interval must be positive."
+            );
+        }
         let n_elite = (self.batch_size as f32 * self.elite_frac).round().floor() as usize;
-        let mut noise_std = Array::from_elem((fcn.params().len(),), self.initial_std);
-        let mut latest_mean_reward = 0.0;
-        for generation in 0..self.generations {
-            let (sorted_th_means, mean_reward) = {
+        let mut noise_std = checkpoint.noise_std.clone();
+        let mut latest_mean_reward = checkpoint.mean_rewards.last().copied().unwrap_or(0.0);
+        let start = Instant::now();
+        let mut stop_reason = StopReason::Generations;
+        for generation in checkpoint.generation..self.generations {
+            let generation_seed = derive_seed(self.seed, generation as u64);
+            let episodes_seed = derive_seed(generation_seed, self.batch_size as u64);
+            let (sorted_th_means, sorted_rewards) = {
                 let mut reward_th_mean_tuples = (0..self.batch_size)
                     .into_par_iter()
                     // .into_iter()
-                    .map(|_| {
+                    .map(|i| {
+                        let candidate_seed = derive_seed(generation_seed, i as u64);
+                        let mut rng = seeded_rng(candidate_seed);
                         let randn_noise: Array1<f32> =
-                            Array::random(fcn.params().len(), StandardNormal);
+                            Array::random_using(policy.params().len(), StandardNormal, &mut rng);
                         let scaled_randn_noise = randn_noise * &noise_std;
-                        let perturbed_params = scaled_randn_noise + fcn.params();
+                        let perturbed_params = scaled_randn_noise + policy.params();
+                        let seed = if self.independent_episodes {
+                            derive_seed(candidate_seed, 0)
+                        } else {
+                            episodes_seed
+                        };
                         (
-                            reward.average_reward(
-                                fcn,
+                            average_reward(
+                                reward,
+                                policy,
                                 &perturbed_params,
                                 self.num_episodes,
                                 self.num_episode_ticks,
+                                self.parallel_episodes,
+                                seed,
                             ),
                             perturbed_params,
                         )
@@ -75,13 +373,11 @@ impl CEO {
                     .collect::<Vec<(f32, Array1<f32>)>>();
                 reward_th_mean_tuples.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
                 reward_th_mean_tuples.reverse();
-                let (rewards, sorted_th_means): (Vec<_>, Vec<_>) =
+                let (sorted_rewards, sorted_th_means): (Vec<_>, Vec<_>) =
                     reward_th_mean_tuples.into_iter().unzip();
-                (
-                    sorted_th_means,
-                    rewards.iter().sum::<f32>() / rewards.len() as f32,
-                )
+                (sorted_th_means, sorted_rewards)
             };
+            let mean_reward = sorted_rewards.iter().sum::<f32>() / sorted_rewards.len() as f32;
             let elite_ths = sorted_th_means
                 .iter()
                 .take(n_elite)
@@ -89,25 +385,268 @@ impl CEO {
                 .collect::<Vec<ArrayView1<f32>>>();
             let elite_ths = stack(Axis(0), &elite_ths)
                 .unwrap()
-                .into_shape((n_elite, fcn.params().len()))
+                .into_shape((n_elite, policy.params().len()))
                 .unwrap();
-            fcn.set_params(elite_ths.mean_axis(Axis(0)).unwrap());
+            policy.set_params(elite_ths.mean_axis(Axis(0)).unwrap());
             noise_std = elite_ths.std_axis(Axis(0), 0.0);
             noise_std += self.noise_factor / (generation + 1) as f32;
-            println!(
-                "generation={} mean_reward={:?} reward_with_current_th={:?}, th_std_mean={:?}",
-                generation + 1,
+            let record = GenerationRecord {
+                generation: generation + 1,
                 mean_reward,
-                reward.average_reward(
-                    fcn,
-                    &fcn.params(),
+                elite_mean_reward: sorted_rewards.iter().take(n_elite).sum::<f32>()
+                    / n_elite as f32,
+                best_reward: sorted_rewards[0],
+                current_params_reward: average_reward(
+                    reward,
+                    policy,
+                    policy.params(),
                     self.num_episodes,
-                    self.num_episode_ticks
+                    self.num_episode_ticks,
+                    self.parallel_episodes,
+                    episodes_seed,
                 ),
-                noise_std.mean(),
-            );
+                noise_std_mean: noise_std.mean().unwrap(),
+                noise_std_min: noise_std.fold(f32::INFINITY, |a, &b| a.min(b)),
+                noise_std_max: noise_std.fold(f32::NEG_INFINITY, |a, &b| a.max(b)),
+                wall_time: start.elapsed().as_secs_f32(),
+            };
+            let go_on = observer.observe(&record);
             latest_mean_reward = mean_reward;
+            checkpoint.generation = generation + 1;
+            checkpoint.params = policy.params().clone();
+            checkpoint.noise_std = noise_std.clone();
+            checkpoint.mean_rewards.push(mean_reward);
+            let early_stop_reason = if go_on {
+                self.stopping.check(&checkpoint.mean_rewards, &record)
+            } else {
+                Some(StopReason::Observer)
+            };
+            if let Some(checkpointing) = checkpointing {
+                // usize::is_multiple_of needs a newer toolchain than the one this crate targets
+                #[allow(clippy::manual_is_multiple_of)]
+                let periodic = checkpoint.generation % checkpointing.every == 0;
+                if periodic
+                    || checkpoint.generation == self.generations
+                    || early_stop_reason.is_some()
+                {
+                    if let Err(e) = checkpoint.save(&checkpointing.path) {
+                        eprintln!("Failed to write checkpoint: {}", e);
+                    }
+                }
+            }
+            if let Some(early_stop_reason) = early_stop_reason {
+                stop_reason = early_stop_reason;
+                break;
+            }
         }
-        Ok((latest_mean_reward, noise_std))
+        Ok((latest_mean_reward, noise_std, stop_reason))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::cross_entropy_optimizing::fcn::{Activation, Initializer, FCN};
+
+    /// Minus the squared distance of the output for a unit input to a target, a quadratic in the
+    /// params of a linear fcn.
+    struct Target(Array1<f32>);
+
+    impl Reward for Target {
+        fn episode_reward(&self, policy: &dyn BoundPolicy, _: usize, _: u64) -> f32 {
+            let error = policy.step(&array![1.0], &mut Array1::zeros(0)) - &self.0;
+            -error.dot(&error)
+        }
+    }
+
+    struct Constant;
+
+    impl Reward for Constant {
+        fn episode_reward(&self, _: &dyn BoundPolicy, _: usize, _: u64) -> f32 {
+            0.0
+        }
+    }
+
+    fn linear_fcn() -> FCN {
+        FCN::new(
+            vec![(1, Activation::Linear), (2, Activation::Linear)],
+            Initializer::Zeros,
+            &mut seeded_rng(0),
+        )
+    }
+
+    fn ceo() -> CEO {
+        CEO {
+            generations: 8,
+            batch_size: 10,
+            num_episodes: 2,
+            num_episode_ticks: 1,
+            elite_frac: 0.3,
+            initial_std: 1.0,
+            noise_factor: 0.1,
+            seed: 5,
+            ..Default::default()
+        }
+    }
+
+    fn checkpointing(name: &str) -> Checkpointing {
+        Checkpointing {
+            path: std::env::temp_dir().join(format!(
+                "stick_solo_{}_{}.json",
+                name,
+                std::process::id()
+            )),
+            every: 100,
+        }
+    }
+
+    #[test]
+    fn resumed_run_equals_uninterrupted_run() {
+        let (ceo, target) = (ceo(), Target(array![1.0, -2.0]));
+        let mut uninterrupted = linear_fcn();
+        let (mean_reward, noise_std, _) = ceo
+            .optimize(&mut uninterrupted, &target, &mut |_: &GenerationRecord| {
+                true
+            })
+            .unwrap();
+        // Stopped after generation 3
+        let checkpointing = checkpointing("resume");
+        let mut interrupted = linear_fcn();
+        let (_, _, stop_reason) = ceo
+            .optimize_with_checkpoints(
+                &mut interrupted,
+                &target,
+                &mut |record: &GenerationRecord| record.generation < 3,
+                &checkpointing,
+            )
+            .unwrap();
+        assert_eq!(stop_reason, StopReason::Observer);
+        let checkpoint = Checkpoint::load(&checkpointing.path).unwrap();
+        fs::remove_file(&checkpointing.path).unwrap();
+        assert_eq!(checkpoint.generation, 3);
+        let mut resumed = linear_fcn();
+        let (resumed_mean_reward, resumed_noise_std, stop_reason) = ceo
+            .resume(
+                &mut resumed,
+                &target,
+                &mut |_: &GenerationRecord| true,
+                checkpoint,
+                None,
+            )
+            .unwrap();
+        assert_eq!(stop_reason, StopReason::Generations);
+        assert_eq!(resumed.params(), uninterrupted.params());
+        assert_eq!(resumed_noise_std, noise_std);
+        assert_eq!(resumed_mean_reward, mean_reward);
+    }
+
+    #[test]
+    #[should_panic(expected = "different configuration")]
+    fn resume_rejects_other_configuration() {
+        let (ceo, target) = (ceo(), Target(array![1.0, -2.0]));
+        let mut fcn = linear_fcn();
+        let checkpoint = ceo.initial_checkpoint(&fcn);
+        let other = CEO {
+            elite_frac: 0.5,
+            ..ceo
+        };
+        other
+            .resume(
+                &mut fcn,
+                &target,
+                &mut |_: &GenerationRecord| true,
+                checkpoint,
+                None,
+            )
+            .unwrap();
+    }
+
+    /// (stop reason, number of generations run)
+    fn stop(ceo: CEO, reward: &(dyn Reward + Sync)) -> (StopReason, usize) {
+        let mut generations = 0;
+        let (_, _, stop_reason) = ceo
+            .optimize(
+                &mut linear_fcn(),
+                reward,
+                &mut |record: &GenerationRecord| {
+                    generations = record.generation;
+                    true
+                },
+            )
+            .unwrap();
+        (stop_reason, generations)
+    }
+
+    #[test]
+    fn stops_on_each_criterion() {
+        let target = Target(array![1.0, -2.0]);
+        let generations = 100;
+        assert_eq!(
+            stop(
+                CEO {
+                    generations,
+                    ..ceo()
+                },
+                &target
+            ),
+            (StopReason::Generations, generations)
+        );
+        assert_eq!(
+            stop(
+                CEO {
+                    generations,
+                    stopping: Stopping {
+                        plateau: Some((3, 0.0)),
+                        ..Default::default()
+                    },
+                    ..ceo()
+                },
+                &Constant
+            ),
+            (StopReason::Plateau, 3)
+        );
+        assert_eq!(
+            stop(
+                CEO {
+                    generations,
+                    initial_std: 0.01,
+                    noise_factor: 0.0,
+                    stopping: Stopping {
+                        min_noise_std: Some(0.05),
+                        ..Default::default()
+                    },
+                    ..ceo()
+                },
+                &target
+            ),
+            (StopReason::NoiseCollapse, 1)
+        );
+        assert_eq!(
+            stop(
+                CEO {
+                    generations,
+                    stopping: Stopping {
+                        max_wall_time: Some(0.0),
+                        ..Default::default()
+                    },
+                    ..ceo()
+                },
+                &target
+            ),
+            (StopReason::WallTime, 1)
+        );
+        let (stop_reason, stopped_at) = stop(
+            CEO {
+                generations,
+                stopping: Stopping {
+                    target_reward: Some(-0.5),
+                    ..Default::default()
+                },
+                ..ceo()
+            },
+            &target,
+        );
+        assert_eq!(stop_reason, StopReason::TargetReward);
+        assert!(1 < stopped_at && stopped_at < generations);
     }
 }
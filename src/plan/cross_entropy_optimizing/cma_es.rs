@@ -0,0 +1,311 @@
+use super::ceo::{average_reward, Reward, StopReason, Stopping};
+use super::policy::Policy;
+use super::telemetry::{GenerationRecord, Observer};
+use crate::plan::{derive_seed, seeded_rng};
+use ndarray::prelude::*;
+use ndarray_rand::{
+    rand_distr::{NormalError, StandardNormal},
+    RandomExt,
+};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+/// Covariance Matrix Adaptation Evolution Strategy.
+/// Unlike CEO, adapts a full covariance of the params and a global step size.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CMAES {
+    pub generations: usize,
+    pub batch_size: usize,
+    pub num_episodes: usize,
+    pub num_episode_ticks: usize,
+    pub initial_step_size: f32,
+    #[serde(default)]
+    pub seed: u64,
+    #[serde(default)]
+    pub stopping: Stopping,
+    /// See `CEO`.
+    #[serde(default)]
+    pub independent_episodes: bool,
+    #[serde(default)]
+    pub parallel_episodes: bool,
+}
+
+impl Default for CMAES {
+    fn default() -> CMAES {
+        CMAES {
+            generations: 300,
+            batch_size: 50,
+            num_episodes: 300,
+            num_episode_ticks: 500,
+            initial_step_size: 1.0,
+            seed: 0,
+            stopping: Stopping::default(),
+            independent_episodes: false,
+            parallel_episodes: false,
+        }
+    }
+}
+
+/// Lower triangular L such that L Lt = m. None if m is not (numerically) positive definite.
+fn cholesky(m: &Array2<f32>) -> Option<Array2<f32>> {
+    let n = m.nrows();
+    let mut l = Array2::<f32>::zeros((n, n));
+    for i in 0..n {
+        for j in 0..=i {
+            let dot = l.slice(s![i, ..j]).dot(&l.slice(s![j, ..j]));
+            if i == j {
+                let diagonal = m[(i, i)] - dot;
+                if diagonal <= 0.0 || !diagonal.is_finite() {
+                    return None;
+                }
+                l[(i, i)] = diagonal.sqrt();
+            } else {
+                l[(i, j)] = (m[(i, j)] - dot) / l[(j, j)];
+            }
+        }
+    }
+    Some(l)
+}
+
+impl CMAES {
+    pub fn optimize<P: Policy>(
+        &self,
+        policy: &mut P,
+        reward: &(dyn Reward<P> + std::marker::Sync),
+        observer: &mut dyn Observer,
+    ) -> Result<(f32, Array1<f32>, StopReason), NormalError> {
+        let n = policy.params().len();
+        let nf = n as f32;
+        let lambda = self.batch_size;
+        let mu = lambda / 2;
+        assert!(mu >= 1, "Batch size too small for CMA-ES.");
+        // Recombination weights
+        let weights = {
+            let weights = (0..mu)
+                .map(|i| ((mu as f32) + 0.5).ln() - ((i + 1) as f32).ln())
+                .collect::<Array1<f32>>();
+            let sum = weights.sum();
+            weights / sum
+        };
+        let mu_eff = 1.0 / weights.mapv(|w| w * w).sum();
+        // Adaptation constants
+        let c_sigma = (mu_eff + 2.0) / (nf + mu_eff + 5.0);
+        let d_sigma = 1.0 + 2.0 * (((mu_eff - 1.0) / (nf + 1.0)).sqrt() - 1.0).max(0.0) + c_sigma;
+        let c_c = (4.0 + mu_eff / nf) / (nf + 4.0 + 2.0 * mu_eff / nf);
+        let c_1 = 2.0 / ((nf + 1.3).powi(2) + mu_eff);
+        let c_mu =
+            (1.0 - c_1).min(2.0 * (mu_eff - 2.0 + 1.0 / mu_eff) / ((nf + 2.0).powi(2) + mu_eff));
+        let expected_norm = nf.sqrt() * (1.0 - 1.0 / (4.0 * nf) + 1.0 / (21.0 * nf * nf));
+        // State
+        let mut mean = policy.params().clone();
+        let mut step_size = self.initial_step_size;
+        let mut covariance = Array2::<f32>::eye(n);
+        let mut p_sigma = Array1::<f32>::zeros(n);
+        let mut p_c = Array1::<f32>::zeros(n);
+        let mut latest_mean_reward = 0.0;
+        let mut mean_rewards = vec![];
+        let start = Instant::now();
+        let mut stop_reason = StopReason::Generations;
+        for generation in 0..self.generations {
+            let generation_seed = derive_seed(self.seed, generation as u64);
+            let episodes_seed = derive_seed(generation_seed, lambda as u64);
+            // Tiny jitter keeps the factorization alive if round off breaks positive definiteness
+            let a = cholesky(&covariance)
+                .or_else(|| cholesky(&(&covariance + &(Array2::<f32>::eye(n) * 1e-6))))
+                .unwrap_or_else(|| Array2::eye(n));
+            let (mean_reward, sorted_rewards, sorted_zs_ys) = {
+                let mut reward_z_y_tuples = (0..lambda)
+                    .into_par_iter()
+                    .map(|i| {
+                        let candidate_seed = derive_seed(generation_seed, i as u64);
+                        let mut rng = seeded_rng(candidate_seed);
+                        let z: Array1<f32> = Array::random_using(n, StandardNormal, &mut rng);
+                        let y = a.dot(&z);
+                        let candidate_params = &mean + &(step_size * &y);
+                        let seed = if self.independent_episodes {
+                            derive_seed(candidate_seed, 0)
+                        } else {
+                            episodes_seed
+                        };
+                        (
+                            average_reward(
+                                reward,
+                                policy,
+                                &candidate_params,
+                                self.num_episodes,
+                                self.num_episode_ticks,
+                                self.parallel_episodes,
+                                seed,
+                            ),
+                            z,
+                            y,
+                        )
+                    })
+                    .collect::<Vec<(f32, Array1<f32>, Array1<f32>)>>();
+                let mean_reward =
+                    reward_z_y_tuples.iter().map(|t| t.0).sum::<f32>() / lambda as f32;
+                reward_z_y_tuples.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+                let (sorted_rewards, sorted_zs_ys): (Vec<_>, Vec<_>) = reward_z_y_tuples
+                    .into_iter()
+                    .take(mu)
+                    .map(|(reward, z, y)| (reward, (z, y)))
+                    .unzip();
+                (mean_reward, sorted_rewards, sorted_zs_ys)
+            };
+            // Weighted recombination of the best mu steps
+            let mut z_w = Array1::<f32>::zeros(n);
+            let mut y_w = Array1::<f32>::zeros(n);
+            let mut weighted_ys = Array2::<f32>::zeros((mu, n));
+            let mut ys = Array2::<f32>::zeros((mu, n));
+            for (i, (z, y)) in sorted_zs_ys.iter().enumerate() {
+                z_w.scaled_add(weights[i], z);
+                y_w.scaled_add(weights[i], y);
+                weighted_ys.row_mut(i).assign(&(weights[i] * y));
+                ys.row_mut(i).assign(y);
+            }
+            mean.scaled_add(step_size, &y_w);
+            // Step size path, with L^-1 y_w = z_w standing in for C^-1/2 y_w
+            p_sigma =
+                (1.0 - c_sigma) * &p_sigma + (c_sigma * (2.0 - c_sigma) * mu_eff).sqrt() * &z_w;
+            let p_sigma_norm = p_sigma.dot(&p_sigma).sqrt();
+            let h_sigma = p_sigma_norm
+                / (1.0 - (1.0 - c_sigma).powi(2 * (generation as i32 + 1))).sqrt()
+                < (1.4 + 2.0 / (nf + 1.0)) * expected_norm;
+            let h_sigma = if h_sigma { 1.0 } else { 0.0 };
+            // Covariance path and rank one + rank mu update
+            p_c = (1.0 - c_c) * &p_c + h_sigma * (c_c * (2.0 - c_c) * mu_eff).sqrt() * &y_w;
+            let p_c_column = p_c.view().insert_axis(Axis(1));
+            let rank_one = p_c_column.dot(&p_c_column.t());
+            let rank_mu = weighted_ys.t().dot(&ys);
+            covariance = (1.0 - c_1 - c_mu + (1.0 - h_sigma) * c_1 * c_c * (2.0 - c_c))
+                * &covariance
+                + c_1 * &rank_one
+                + c_mu * &rank_mu;
+            step_size *= ((c_sigma / d_sigma) * (p_sigma_norm / expected_norm - 1.0)).exp();
+            policy.set_params(mean.clone());
+            let params_std = step_size * covariance.diag().mapv(f32::sqrt);
+            let record = GenerationRecord {
+                generation: generation + 1,
+                mean_reward,
+                elite_mean_reward: sorted_rewards.iter().sum::<f32>() / mu as f32,
+                best_reward: sorted_rewards[0],
+                current_params_reward: average_reward(
+                    reward,
+                    policy,
+                    policy.params(),
+                    self.num_episodes,
+                    self.num_episode_ticks,
+                    self.parallel_episodes,
+                    episodes_seed,
+                ),
+                noise_std_mean: params_std.mean().unwrap(),
+                noise_std_min: params_std.fold(f32::INFINITY, |a, &b| a.min(b)),
+                noise_std_max: params_std.fold(f32::NEG_INFINITY, |a, &b| a.max(b)),
+                wall_time: start.elapsed().as_secs_f32(),
+            };
+            let go_on = observer.observe(&record);
+            latest_mean_reward = mean_reward;
+            mean_rewards.push(mean_reward);
+            let early_stop_reason = if go_on {
+                self.stopping.check(&mean_rewards, &record)
+            } else {
+                Some(StopReason::Observer)
+            };
+            if let Some(early_stop_reason) = early_stop_reason {
+                stop_reason = early_stop_reason;
+                break;
+            }
+        }
+        Ok((
+            latest_mean_reward,
+            step_size * covariance.diag().mapv(f32::sqrt),
+            stop_reason,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::cross_entropy_optimizing::fcn::{Activation, Initializer, FCN};
+    use crate::plan::cross_entropy_optimizing::policy::BoundPolicy;
+    use crate::plan::cross_entropy_optimizing::telemetry::GenerationRecord;
+
+    /// Minus the squared distance of the output for a unit input to a target, a quadratic in the
+    /// params of a linear fcn.
+    struct Target(Array1<f32>);
+
+    impl Reward for Target {
+        fn episode_reward(&self, policy: &dyn BoundPolicy, _: usize, _: u64) -> f32 {
+            let error = policy.step(&array![1.0], &mut Array1::zeros(0)) - &self.0;
+            -error.dot(&error)
+        }
+    }
+
+    fn linear_fcn() -> FCN {
+        FCN::new(
+            vec![(1, Activation::Linear), (2, Activation::Linear)],
+            Initializer::Zeros,
+            &mut seeded_rng(0),
+        )
+    }
+
+    fn cma_es() -> CMAES {
+        CMAES {
+            generations: 150,
+            batch_size: 12,
+            num_episodes: 1,
+            num_episode_ticks: 1,
+            initial_step_size: 0.5,
+            seed: 3,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn cholesky_reconstructs_spd_matrix() {
+        let m = array![
+            [4.0, 12.0, -16.0],
+            [12.0, 37.0, -43.0],
+            [-16.0, -43.0, 98.0]
+        ];
+        let l = cholesky(&m).unwrap();
+        assert_eq!(
+            l,
+            array![[2.0, 0.0, 0.0], [6.0, 1.0, 0.0], [-8.0, 5.0, 3.0]]
+        );
+        assert_eq!(l.dot(&l.t()), m);
+        assert!(cholesky(&array![[1.0, 2.0], [2.0, 1.0]]).is_none());
+    }
+
+    #[test]
+    fn converges_on_quadratic() {
+        let target = Target(array![1.0, -2.0]);
+        let mut fcn = linear_fcn();
+        let (_, _, stop_reason) = cma_es()
+            .optimize(&mut fcn, &target, &mut |_: &GenerationRecord| true)
+            .unwrap();
+        assert_eq!(stop_reason, StopReason::Generations);
+        let reward = target.average_reward(&fcn, fcn.params(), 1, 1, 0);
+        assert!(reward > -1e-4, "{}", reward);
+    }
+
+    #[test]
+    fn same_seed_same_params() {
+        let target = Target(array![1.0, -2.0]);
+        let run = || {
+            let mut fcn = linear_fcn();
+            let cma_es = CMAES {
+                generations: 10,
+                ..cma_es()
+            };
+            cma_es
+                .optimize(&mut fcn, &target, &mut |_: &GenerationRecord| true)
+                .unwrap();
+            fcn.params().clone()
+        };
+        assert_eq!(run(), run());
+    }
+}
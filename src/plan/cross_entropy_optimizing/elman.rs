@@ -0,0 +1,253 @@
+use super::fcn::{Activation, Initializer};
+use super::policy::{BoundPolicy, Policy};
+use ndarray::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// Single hidden layer recurrent network,
+/// h_t = hidden_activation(W_h [x_t; h_(t-1)] + b_h), y_t = output_activation(W_y h_t + b_y).
+/// Params are laid out as W_h, b_h, W_y, b_y, with the matrices row major.
+/// Checked on load like on creation.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(try_from = "UncheckedElmanRNN")]
+pub struct ElmanRNN {
+    input_dof: usize,
+    hidden_dof: usize,
+    output_dof: usize,
+    hidden_activation: Activation,
+    output_activation: Activation,
+    initializer: Initializer,
+    params: Array1<f32>,
+}
+
+/// An ElmanRNN as saved, before its activations and params len are checked.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UncheckedElmanRNN {
+    input_dof: usize,
+    hidden_dof: usize,
+    output_dof: usize,
+    hidden_activation: Activation,
+    output_activation: Activation,
+    #[serde(default)]
+    initializer: Initializer,
+    params: Array1<f32>,
+}
+
+impl TryFrom<UncheckedElmanRNN> for ElmanRNN {
+    type Error = String;
+
+    fn try_from(rnn: UncheckedElmanRNN) -> Result<ElmanRNN, String> {
+        let UncheckedElmanRNN {
+            input_dof,
+            hidden_dof,
+            output_dof,
+            hidden_activation,
+            output_activation,
+            initializer,
+            params,
+        } = rnn;
+        if input_dof == 0 || hidden_dof == 0 || output_dof == 0 {
+            return Err("Trying to create a model with an empty layer.".to_string());
+        }
+        hidden_activation.check(hidden_dof)?;
+        output_activation.check(output_dof)?;
+        if params.len() != (input_dof + hidden_dof + 1) * hidden_dof + (hidden_dof + 1) * output_dof
+        {
+            return Err("Invalid params len for rnn".to_string());
+        }
+        Ok(ElmanRNN {
+            input_dof,
+            hidden_dof,
+            output_dof,
+            hidden_activation,
+            output_activation,
+            initializer,
+            params,
+        })
+    }
+}
+
+impl ElmanRNN {
+    pub fn new<R: Rng>(
+        (input_dof, hidden_dof, output_dof): (usize, usize, usize),
+        hidden_activation: Activation,
+        output_activation: Activation,
+        initializer: Initializer,
+        rng: &mut R,
+    ) -> ElmanRNN {
+        assert!(
+            input_dof > 0 && hidden_dof > 0 && output_dof > 0,
+            "Trying to create a model with an empty layer."
+        );
+        hidden_activation.assert_valid(hidden_dof);
+        output_activation.assert_valid(output_dof);
+        let params = initializer.initial_params(
+            &[
+                (input_dof + hidden_dof, hidden_dof),
+                (hidden_dof, output_dof),
+            ],
+            rng,
+        );
+        ElmanRNN {
+            input_dof,
+            hidden_dof,
+            output_dof,
+            hidden_activation,
+            output_activation,
+            initializer,
+            params,
+        }
+    }
+
+    pub fn initializer(&self) -> &Initializer {
+        &self.initializer
+    }
+}
+
+impl Policy for ElmanRNN {
+    fn params(&self) -> &Array1<f32> {
+        &self.params
+    }
+
+    fn set_params(&mut self, new_params: Array1<f32>) {
+        self.params = new_params;
+    }
+
+    fn initial_state(&self) -> Array1<f32> {
+        Array1::zeros(self.hidden_dof)
+    }
+
+    fn bind<'a>(&'a self, params: &'a Array1<f32>) -> Box<dyn BoundPolicy + 'a> {
+        assert_eq!(
+            params.len(),
+            self.params.len(),
+            "Invalid params len for rnn"
+        );
+        let (i, h, o) = (self.input_dof, self.hidden_dof, self.output_dof);
+        let mut params_offset = 0;
+        let hidden_matrix = params
+            .slice(s![params_offset..(params_offset + h * (i + h))])
+            .into_shape((h, i + h))
+            .unwrap();
+        params_offset += h * (i + h);
+        let hidden_bias = params.slice(s![params_offset..(params_offset + h)]);
+        params_offset += h;
+        let output_matrix = params
+            .slice(s![params_offset..(params_offset + o * h)])
+            .into_shape((o, h))
+            .unwrap();
+        params_offset += o * h;
+        let output_bias = params.slice(s![params_offset..(params_offset + o)]);
+        Box::new(BoundElmanRNN {
+            rnn: self,
+            hidden_matrix,
+            hidden_bias,
+            output_matrix,
+            output_bias,
+        })
+    }
+}
+
+/// An ElmanRNN along with views of the weight matrices and biases of a params vector.
+pub struct BoundElmanRNN<'a> {
+    rnn: &'a ElmanRNN,
+    hidden_matrix: ArrayView2<'a, f32>,
+    hidden_bias: ArrayView1<'a, f32>,
+    output_matrix: ArrayView2<'a, f32>,
+    output_bias: ArrayView1<'a, f32>,
+}
+
+impl<'a> BoundPolicy for BoundElmanRNN<'a> {
+    fn initial_state(&self) -> Array1<f32> {
+        self.rnn.initial_state()
+    }
+
+    fn step(&self, input: &Array1<f32>, state: &mut Array1<f32>) -> Array1<f32> {
+        let (i, h) = (self.rnn.input_dof, self.rnn.hidden_dof);
+        assert_eq!(input.len(), i, "Invalid input len for rnn");
+        assert_eq!(state.len(), h, "Invalid state len for rnn");
+        let mut input_and_state = Array1::zeros(i + h);
+        input_and_state.slice_mut(s![..i]).assign(input);
+        input_and_state.slice_mut(s![i..]).assign(state);
+        *state = self
+            .rnn
+            .hidden_activation
+            .apply_all(&(self.hidden_matrix.dot(&input_and_state) + self.hidden_bias));
+        self.rnn
+            .output_activation
+            .apply_all(&(self.output_matrix.dot(state) + self.output_bias))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::seeded_rng;
+
+    /// h_t = x_t + 0.5 h_(t-1), y_t = 2 h_t + 1.
+    fn decaying_rnn() -> ElmanRNN {
+        ElmanRNN::new(
+            (1, 1, 1),
+            Activation::Linear,
+            Activation::Linear,
+            Initializer::Params(array![1.0, 0.5, 0.0, 2.0, 1.0]),
+            &mut seeded_rng(0),
+        )
+    }
+
+    #[test]
+    fn step_carries_the_hidden_state() {
+        let rnn = decaying_rnn();
+        let mut state = rnn.initial_state();
+        let outputs = [1.0, 0.0, 0.0]
+            .iter()
+            .map(|&x| rnn.step(&array![x], &mut state)[0])
+            .collect::<Vec<f32>>();
+        assert_eq!(outputs, vec![3.0, 2.0, 1.5]);
+        assert_eq!(state, array![0.25]);
+        // A fresh episode starts over
+        let mut state = rnn.initial_state();
+        assert_eq!(rnn.step(&array![1.0], &mut state), array![3.0]);
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        let rnn = ElmanRNN::new(
+            (3, 4, 2),
+            Activation::Tanh,
+            Activation::Bounded(vec![(-1.0, 1.0), (0.0, 2.0)]),
+            Initializer::XavierUniform,
+            &mut seeded_rng(0),
+        );
+        let loaded: ElmanRNN = serde_json::from_str(&serde_json::to_string(&rnn).unwrap()).unwrap();
+        assert_eq!(loaded.params(), rnn.params());
+        let (mut state, mut loaded_state) = (rnn.initial_state(), loaded.initial_state());
+        for input in [array![0.1, -0.2, 0.3], array![1.0, 0.0, -1.0]] {
+            assert_eq!(
+                loaded.step(&input, &mut loaded_state),
+                rnn.step(&input, &mut state)
+            );
+        }
+        assert_eq!(loaded_state, state);
+    }
+
+    #[test]
+    fn load_checks_activations_and_params_len() {
+        let json = |output_activation: &str, num_params: usize| {
+            format!(
+                r#"{{"input_dof":1,"hidden_dof":1,"output_dof":2,"hidden_activation":"Tanh","output_activation":{},"params":{{"v":1,"dim":[{}],"data":{:?}}}}}"#,
+                output_activation,
+                num_params,
+                vec![0.0; num_params]
+            )
+        };
+        let bounded = r#"{"Bounded":[[-1.0,1.0],[0.0,2.0]]}"#;
+        assert!(serde_json::from_str::<ElmanRNN>(&json(bounded, 7)).is_ok());
+        let error =
+            serde_json::from_str::<ElmanRNN>(&json(r#"{"Bounded":[[-1.0,1.0]]}"#, 7)).unwrap_err();
+        assert!(error.to_string().contains("Bad bounded arguments len."));
+        let error = serde_json::from_str::<ElmanRNN>(&json(bounded, 6)).unwrap_err();
+        assert!(error.to_string().contains("Invalid params len for rnn"));
+    }
+}
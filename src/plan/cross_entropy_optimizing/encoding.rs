@@ -0,0 +1,188 @@
+use super::utils::decode;
+use crate::act::one_holding_switchable_nr_couple::OneHoldingSwitchableNRCouple;
+use crate::act::switchable_nr::Side;
+use bevy::prelude::*;
+use ndarray::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// Lengths and positions are divided by the total length of both chains,
+/// and positions are relative to the holding origin.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Feature {
+    HoldingLs,
+    NonHoldingLs,
+    /// Of the non holding goal.
+    RelativeGoal,
+    HoldingQs,
+    NonHoldingQs,
+    CenterOfMass,
+    /// -1 if the holding side is left, 1 if right.
+    PivotSide,
+    /// The next K holds after the non holding goal, zero padded when fewer are given.
+    NextHolds(usize),
+    /// (min, max) of each joint, unbounded ends as -pi or pi.
+    HoldingQClamps,
+    NonHoldingQClamps,
+}
+
+impl Feature {
+    pub fn len(&self, holding_n: usize, non_holding_n: usize) -> usize {
+        match self {
+            Feature::HoldingLs | Feature::HoldingQs => holding_n,
+            Feature::NonHoldingLs | Feature::NonHoldingQs => non_holding_n,
+            Feature::RelativeGoal | Feature::CenterOfMass => 2,
+            Feature::PivotSide => 1,
+            Feature::NextHolds(k) => 2 * k,
+            Feature::HoldingQClamps => 2 * holding_n,
+            Feature::NonHoldingQClamps => 2 * non_holding_n,
+        }
+    }
+}
+
+/// Observation of a one holding couple and its goals as a network input,
+/// the concatenation of the features in order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Encoder {
+    pub features: Vec<Feature>,
+}
+
+/// Same layout as `utils::encode`.
+impl Default for Encoder {
+    fn default() -> Encoder {
+        Encoder {
+            features: vec![
+                Feature::HoldingLs,
+                Feature::NonHoldingLs,
+                Feature::RelativeGoal,
+            ],
+        }
+    }
+}
+
+impl Encoder {
+    pub fn len(&self, holding_n: usize, non_holding_n: usize) -> usize {
+        self.features
+            .iter()
+            .map(|feature| feature.len(holding_n, non_holding_n))
+            .sum()
+    }
+
+    /// Where each feature sits in the input.
+    pub fn layout(&self, holding_n: usize, non_holding_n: usize) -> Vec<(Feature, Range<usize>)> {
+        let mut offset = 0;
+        self.features
+            .iter()
+            .map(|&feature| {
+                let len = feature.len(holding_n, non_holding_n);
+                offset += len;
+                (feature, (offset - len)..offset)
+            })
+            .collect()
+    }
+
+    /// Where the first occurrence of feature sits in the input, if at all.
+    pub fn range(
+        &self,
+        feature: Feature,
+        holding_n: usize,
+        non_holding_n: usize,
+    ) -> Option<Range<usize>> {
+        self.layout(holding_n, non_holding_n)
+            .into_iter()
+            .find(|(f, _)| *f == feature)
+            .map(|(_, range)| range)
+    }
+
+    /// Returns the input and the scale.
+    pub fn encode(
+        &self,
+        agent: &OneHoldingSwitchableNRCouple,
+        non_holding_goal: &Vec2,
+        next_holds: &[Vec2],
+    ) -> (Array1<f32>, f32) {
+        let (holding_n, holding_origin, holding_ls, holding_qs, holding_q_clamps, holding_side) =
+            agent.holding().get_current_state();
+        let (non_holding_n, _, non_holding_ls, non_holding_qs, non_holding_q_clamps, _) =
+            agent.non_holding().get_current_state();
+        let scale = holding_ls.sum() + non_holding_ls.sum();
+        let relative = |point: &Vec2| (*point - *holding_origin) / scale;
+        let clamps = |q_clamps: &Array1<(f32, f32)>| {
+            let pi = std::f32::consts::PI;
+            q_clamps
+                .iter()
+                .flat_map(|(min, max)| vec![min.max(-pi), max.min(pi)])
+                .collect::<Vec<f32>>()
+        };
+        let mut encoding = Vec::with_capacity(self.len(holding_n, non_holding_n));
+        for feature in &self.features {
+            match feature {
+                Feature::HoldingLs => encoding.extend(holding_ls.iter().map(|l| l / scale)),
+                Feature::NonHoldingLs => encoding.extend(non_holding_ls.iter().map(|l| l / scale)),
+                Feature::RelativeGoal => {
+                    let goal = relative(non_holding_goal);
+                    encoding.extend(&[goal[0], goal[1]]);
+                }
+                Feature::HoldingQs => encoding.extend(holding_qs.iter()),
+                Feature::NonHoldingQs => encoding.extend(non_holding_qs.iter()),
+                Feature::CenterOfMass => {
+                    let com = relative(&agent.get_center_of_mass());
+                    encoding.extend(&[com[0], com[1]]);
+                }
+                Feature::PivotSide => encoding.push(match holding_side {
+                    Side::Left => -1.0,
+                    Side::Right => 1.0,
+                }),
+                Feature::NextHolds(k) => {
+                    for i in 0..*k {
+                        match next_holds.get(i) {
+                            Some(hold) => {
+                                let hold = relative(hold);
+                                encoding.extend(&[hold[0], hold[1]]);
+                            }
+                            None => encoding.extend(&[0.0, 0.0]),
+                        }
+                    }
+                }
+                Feature::HoldingQClamps => encoding.extend(clamps(holding_q_clamps)),
+                Feature::NonHoldingQClamps => encoding.extend(clamps(non_holding_q_clamps)),
+            }
+        }
+        (Array1::from(encoding), scale)
+    }
+}
+
+/// Network output as a holding goal.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum Decoder {
+    /// Scaled, relative to the holding origin; same as `utils::decode`.
+    #[default]
+    RelativeGoal,
+    /// Scaled, relative to the current end of the holding chain.
+    EndDisplacement,
+}
+
+impl Decoder {
+    pub fn output_dof(&self) -> usize {
+        2
+    }
+
+    pub fn decode(
+        &self,
+        forward_pass: &Array1<f32>,
+        scale: f32,
+        agent: &OneHoldingSwitchableNRCouple,
+    ) -> Vec2 {
+        assert_eq!(
+            forward_pass.len(),
+            self.output_dof(),
+            "Invalid output len for decoder"
+        );
+        let origin = match self {
+            Decoder::RelativeGoal => *agent.holding().get_current_state().1,
+            Decoder::EndDisplacement => agent.holding().get_last_vertex(),
+        };
+        decode(forward_pass, scale, origin)
+    }
+}
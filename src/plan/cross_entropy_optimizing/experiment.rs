@@ -1,12 +1,23 @@
 use super::ceo::CEO;
+use super::cma_es::CMAES;
+use super::encoding::{Decoder, Encoder};
 use super::fcn::FCN;
 use super::world::World;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
-pub struct Experiment {
-    pub fcn: FCN,
-    pub ceo: CEO,
+pub struct Experiment<P = FCN> {
+    /// Named fcn in experiments saved before policies other than fcns were supported.
+    #[serde(alias = "fcn")]
+    pub policy: P,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ceo: Option<CEO>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cma_es: Option<CMAES>,
     pub world: World,
+    #[serde(default)]
+    pub encoder: Encoder,
+    #[serde(default)]
+    pub decoder: Decoder,
 }
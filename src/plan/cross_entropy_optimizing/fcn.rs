@@ -1,6 +1,7 @@
 use ndarray::prelude::*;
-use ndarray_rand::rand_distr::Uniform;
+use ndarray_rand::rand_distr::{Normal, Uniform};
 use ndarray_rand::RandomExt;
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
@@ -9,16 +10,257 @@ use std::fmt;
 pub enum Activation {
     Linear,
     LeakyReLu(f32),
+    /// (sigmoid(x) - min) / (max - min), which is not into (min, max); kept as is so that saved
+    /// experiments behave the same. See ScaledSigmoid.
     Sigmoid(f32, f32),
+    /// Logistic sigmoid scaled into (min, max).
+    ScaledSigmoid(f32, f32),
+    Tanh,
+    ReLu,
+    Softplus,
+    /// Exponential linear unit with the given alpha.
+    Elu(f32),
+    /// Logistic sigmoid scaled into a box, unit i into (min_i, max_i).
+    Bounded(Vec<(f32, f32)>),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+fn sigmoid(x: f32) -> f32 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+impl Activation {
+    /// Value of unit i of a layer with pre activation x.
+    pub fn apply(&self, i: usize, x: f32) -> f32 {
+        match self {
+            Activation::Linear => x,
+            Activation::LeakyReLu(leak) => {
+                if x > 0.0 {
+                    x
+                } else {
+                    x * leak
+                }
+            }
+            Activation::Sigmoid(min, max) => (sigmoid(x) - min) / (max - min),
+            Activation::ScaledSigmoid(min, max) => min + (max - min) * sigmoid(x),
+            Activation::Tanh => x.tanh(),
+            Activation::ReLu => x.max(0.0),
+            // log(1 + e^x), without overflow for large x
+            Activation::Softplus => x.max(0.0) + (-x.abs()).exp().ln_1p(),
+            Activation::Elu(alpha) => {
+                if x > 0.0 {
+                    x
+                } else {
+                    alpha * x.exp_m1()
+                }
+            }
+            Activation::Bounded(bounds) => {
+                let (min, max) = bounds[i];
+                min + (max - min) * sigmoid(x)
+            }
+        }
+    }
+
+    /// Derivative of unit i w.r.t. its pre activation x.
+    pub fn derivative(&self, i: usize, x: f32) -> f32 {
+        match self {
+            Activation::Linear => 1.0,
+            Activation::LeakyReLu(leak) => {
+                if x > 0.0 {
+                    1.0
+                } else {
+                    *leak
+                }
+            }
+            Activation::Sigmoid(min, max) => sigmoid(x) * (1.0 - sigmoid(x)) / (max - min),
+            Activation::ScaledSigmoid(min, max) => (max - min) * sigmoid(x) * (1.0 - sigmoid(x)),
+            Activation::Tanh => 1.0 - x.tanh().powi(2),
+            Activation::ReLu => {
+                if x > 0.0 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Activation::Softplus => sigmoid(x),
+            Activation::Elu(alpha) => {
+                if x > 0.0 {
+                    1.0
+                } else {
+                    alpha * x.exp()
+                }
+            }
+            Activation::Bounded(bounds) => {
+                let (min, max) = bounds[i];
+                (max - min) * sigmoid(x) * (1.0 - sigmoid(x))
+            }
+        }
+    }
+
+    pub(crate) fn apply_all(&self, xs: &Array1<f32>) -> Array1<f32> {
+        Array1::from_shape_fn(xs.len(), |i| self.apply(i, xs[i]))
+    }
+
+    fn derivative_all(&self, xs: &Array1<f32>) -> Array1<f32> {
+        Array1::from_shape_fn(xs.len(), |i| self.derivative(i, xs[i]))
+    }
+
+    /// Each row holds the pre activations of a whole layer.
+    fn apply_rows(&self, xs: &Array2<f32>) -> Array2<f32> {
+        Array2::from_shape_fn(xs.dim(), |(r, i)| self.apply(i, xs[(r, i)]))
+    }
+
+    /// What is wrong with the arguments for a layer of dof units, if anything.
+    pub(crate) fn check(&self, dof: usize) -> Result<(), String> {
+        match self {
+            Activation::Sigmoid(min, max) | Activation::ScaledSigmoid(min, max) if min >= max => {
+                Err("Bad sigmoid arguments.".to_string())
+            }
+            Activation::Bounded(bounds) if bounds.len() != dof => {
+                Err("Bad bounded arguments len.".to_string())
+            }
+            Activation::Bounded(bounds) if bounds.iter().any(|(min, max)| min >= max) => {
+                Err("Bad bounded arguments.".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn assert_valid(&self, dof: usize) {
+        if let Err(e) = self.check(dof) {
+            panic!("{}", e);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
+pub enum Initializer {
+    /// Every weight and bias from U(min, max).
+    Uniform(f32, f32),
+    /// Glorot, weights from U(-a, a) with a = sqrt(6 / (fan_in + fan_out)); zero biases.
+    XavierUniform,
+    /// Kaiming, weights from N(0, 2 / fan_in); zero biases.
+    HeNormal,
+    Zeros,
+    /// These exact params, e.g. those of another fcn; see `Initializer::from_fcn`.
+    Params(Array1<f32>),
+}
+
+/// The scheme used before initializers were configurable.
+impl Default for Initializer {
+    fn default() -> Initializer {
+        Initializer::Uniform(0.0, 1.0)
+    }
+}
+
+impl Initializer {
+    pub fn from_fcn(fcn: &FCN) -> Initializer {
+        Initializer::Params(fcn.params().clone())
+    }
+
+    /// Params laid out as consecutive (fan_out x fan_in weight matrix, fan_out bias) blocks.
+    pub(crate) fn initial_params<R: Rng>(
+        &self,
+        fans: &[(usize, usize)],
+        rng: &mut R,
+    ) -> Array1<f32> {
+        let num_params: usize = fans
+            .iter()
+            .map(|(fan_in, fan_out)| (fan_in + 1) * fan_out)
+            .sum();
+        match self {
+            Initializer::Uniform(min, max) => {
+                assert!(min < max, "Bad uniform initializer arguments.");
+                Array::random_using(num_params, Uniform::new(min, max), rng)
+            }
+            Initializer::XavierUniform | Initializer::HeNormal => {
+                let mut params = Array1::zeros(num_params);
+                let mut params_offset = 0;
+                for &(fan_in, fan_out) in fans {
+                    let matrix: Array1<f32> = match self {
+                        Initializer::XavierUniform => {
+                            let a = (6.0 / (fan_in + fan_out) as f32).sqrt();
+                            Array::random_using(fan_in * fan_out, Uniform::new(-a, a), rng)
+                        }
+                        _ => {
+                            let std = (2.0 / fan_in as f32).sqrt();
+                            Array::random_using(
+                                fan_in * fan_out,
+                                Normal::new(0.0, std).unwrap(),
+                                rng,
+                            )
+                        }
+                    };
+                    params
+                        .slice_mut(s![params_offset..(params_offset + matrix.len())])
+                        .assign(&matrix);
+                    // Biases stay zero
+                    params_offset += (fan_in + 1) * fan_out;
+                }
+                params
+            }
+            Initializer::Zeros => Array1::zeros(num_params),
+            Initializer::Params(params) => {
+                assert_eq!(
+                    params.len(),
+                    num_params,
+                    "Invalid params len for initializer"
+                );
+                params.clone()
+            }
+        }
+    }
+}
+
+/// Checked on load like on creation, see `UncheckedFCN`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(try_from = "UncheckedFCN")]
 pub struct FCN {
     layers: Vec<(usize, Activation)>,
+    initializer: Initializer,
+    params: Array1<f32>,
+}
+
+/// An FCN as saved, before its layers and params len are checked.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UncheckedFCN {
+    layers: Vec<(usize, Activation)>,
+    #[serde(default)]
+    initializer: Initializer,
     params: Array1<f32>,
 }
 
+impl TryFrom<UncheckedFCN> for FCN {
+    type Error = String;
+
+    fn try_from(fcn: UncheckedFCN) -> Result<FCN, String> {
+        let UncheckedFCN {
+            layers,
+            initializer,
+            params,
+        } = fcn;
+        if layers.len() < 2 {
+            return Err("Trying to create a model with less than 2 layers.".to_string());
+        }
+        for (dof, activation) in &layers {
+            activation.check(*dof)?;
+        }
+        let num_params: usize = layers
+            .windows(2)
+            .map(|pair| (pair[0].0 + 1) * pair[1].0)
+            .sum();
+        if params.len() != num_params {
+            return Err("Invalid params len for fcn".to_string());
+        }
+        Ok(FCN {
+            layers,
+            initializer,
+            params,
+        })
+    }
+}
+
 impl fmt::Display for FCN {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -31,26 +273,26 @@ impl fmt::Display for FCN {
 }
 
 impl FCN {
-    pub fn new(layers: Vec<(usize, Activation)>) -> FCN {
+    pub fn new<R: Rng>(
+        layers: Vec<(usize, Activation)>,
+        initializer: Initializer,
+        rng: &mut R,
+    ) -> FCN {
         assert!(
             layers.len() >= 2,
             "Trying to create a model with less than 2 layers."
         );
-        let num_params = {
-            let mut num_params = 0;
-            for i in 1..layers.len() {
-                num_params += (layers[i - 1].0 + 1) * layers[i].0;
-                if let Activation::Sigmoid(min, max) = layers[i].1 {
-                    assert!(min < max, "Bad sigmoid arguments.");
-                }
-            }
-            num_params
+        let mut fcn = FCN {
+            layers,
+            initializer,
+            params: Array1::zeros(0),
         };
-        FCN {
-            layers: layers,
-            params: //Array::from_elem((num_params,), 0.01),
-            Array::random(num_params, Uniform::new(0.0, 1.0)),
-        }
+        fcn.reinitialize(rng);
+        fcn
+    }
+
+    pub fn initializer(&self) -> &Initializer {
+        &self.initializer
     }
 
     pub fn params(&self) -> &Array1<f32> {
@@ -61,58 +303,359 @@ impl FCN {
         self.params = new_params;
     }
 
-    /// Clones input but not params.
-    pub fn at_with(&self, input: &Array1<f32>, params: &Array1<f32>) -> Array1<f32> {
+    /// Draws fresh params from the initializer, e.g. after the layers were edited.
+    pub fn reinitialize<R: Rng>(&mut self, rng: &mut R) {
+        for (dof, activation) in &self.layers {
+            activation.assert_valid(*dof);
+        }
+        let fans = (1..self.layers.len())
+            .map(|i| (self.layers[i - 1].0, self.layers[i].0))
+            .collect::<Vec<(usize, usize)>>();
+        self.params = self.initializer.initial_params(&fans, rng);
+    }
+
+    /// Weight matrix and bias of layer i, which start at params_offset.
+    fn weights_and_bias<'a>(
+        &self,
+        params: &'a Array1<f32>,
+        i: usize,
+        params_offset: usize,
+    ) -> (ArrayView2<'a, f32>, ArrayView1<'a, f32>) {
+        let prev_layer_dof = self.layers[i - 1].0;
+        let curr_layer_dof = self.layers[i].0;
+        let matrix = params
+            .slice(s![
+                params_offset..(params_offset + prev_layer_dof * curr_layer_dof)
+            ])
+            .into_shape((curr_layer_dof, prev_layer_dof))
+            .unwrap();
+        let bias_offset = params_offset + prev_layer_dof * curr_layer_dof;
+        let bias = params.slice(s![bias_offset..(bias_offset + curr_layer_dof)]);
+        (matrix, bias)
+    }
+
+    fn assert_valid(&self, input: &Array1<f32>, params: &Array1<f32>) {
         assert_eq!(input.len(), self.layers[0].0, "Invalid input len for fcn");
         assert_eq!(
             params.len(),
             self.params.len(),
             "Invalid params len for fcn"
         );
+    }
+
+    /// Parses the weight matrices and biases out of params once, for many forward passes.
+    pub fn bind<'a>(&'a self, params: &'a Array1<f32>) -> BoundFCN<'a> {
+        assert_eq!(
+            params.len(),
+            self.params.len(),
+            "Invalid params len for fcn"
+        );
         let mut params_offset = 0;
-        let mut output = input.to_owned();
-        output = match &self.layers[0].1 {
-            Activation::Linear => output,
-            Activation::LeakyReLu(leak) => output.mapv(|e| if e > 0.0 { e } else { e * leak }),
-            Activation::Sigmoid(min, max) => output.mapv(|e| {
-                let zero_to_one = 1.0 / (1.0 + (-e).exp());
-                (zero_to_one - min) / (max - min)
-            }),
-        };
+        let mut weights_and_biases = vec![];
         for i in 1..self.layers.len() {
-            let prev_layer_dof = self.layers[i - 1].0;
-            let curr_layer_dof = self.layers[i].0;
-            let curr_layer_activation = &self.layers[i].1;
-            // let now = Instant::now();
-            let matrix = params
-                .slice(s![
-                    params_offset..(params_offset + prev_layer_dof * curr_layer_dof)
-                ])
-                .into_shape((curr_layer_dof, prev_layer_dof))
-                .unwrap();
-            params_offset += prev_layer_dof * curr_layer_dof;
-            let bias = params
-                .slice(s![params_offset..(params_offset + curr_layer_dof)])
-                .into_shape(curr_layer_dof)
-                .unwrap();
-            // println!("1:{}", now.elapsed().as_nanos());
-            // let now = Instant::now();
-            output = matrix.dot(&output) + bias;
-            output = match curr_layer_activation {
-                Activation::Linear => output,
-                Activation::LeakyReLu(leak) => output.mapv(|e| if e > 0.0 { e } else { e * leak }),
-                Activation::Sigmoid(min, max) => output.mapv(|e| {
-                    let zero_to_one = 1.0 / (1.0 + (-e).exp());
-                    (zero_to_one - min) / (max - min)
-                }),
-            };
-            params_offset += curr_layer_dof;
-            // println!("2:{}", now.elapsed().as_nanos());
+            let (matrix, bias) = self.weights_and_bias(params, i, params_offset);
+            params_offset += matrix.len() + bias.len();
+            weights_and_biases.push((matrix, bias));
         }
-        output
+        BoundFCN {
+            layers: &self.layers,
+            weights_and_biases,
+        }
+    }
+
+    /// Clones input but not params.
+    pub fn at_with(&self, input: &Array1<f32>, params: &Array1<f32>) -> Array1<f32> {
+        self.bind(params).at(input)
+    }
+
+    /// Each row of inputs is an input, each row of the result its output.
+    pub fn at_batch_with(&self, inputs: &Array2<f32>, params: &Array1<f32>) -> Array2<f32> {
+        self.bind(params).at_batch(inputs)
+    }
+
+    /// Reverse mode pass, i.e. the vector jacobian product output_gradient^T d fcn(input)/d params,
+    /// output_gradient being computed from the output of the forward pass.
+    /// Returns the output and the gradient, the latter laid out like params.
+    pub fn backward_with<F: FnOnce(&Array1<f32>) -> Array1<f32>>(
+        &self,
+        input: &Array1<f32>,
+        params: &Array1<f32>,
+        output_gradient: F,
+    ) -> (Array1<f32>, Array1<f32>) {
+        self.assert_valid(input, params);
+        // Forward, keeping pre activations and activations of every layer
+        let mut params_offsets = vec![0];
+        let activation = &self.layers[0].1;
+        let mut pre_activations = vec![input.to_owned()];
+        let mut activations = vec![activation.apply_all(input)];
+        for i in 1..self.layers.len() {
+            let (matrix, bias) = self.weights_and_bias(params, i, params_offsets[i - 1]);
+            params_offsets.push(params_offsets[i - 1] + matrix.len() + bias.len());
+            let pre_activation = matrix.dot(&activations[i - 1]) + bias;
+            let activation = &self.layers[i].1;
+            activations.push(activation.apply_all(&pre_activation));
+            pre_activations.push(pre_activation);
+        }
+        // Backward
+        let output = activations.pop().unwrap();
+        let mut activation_gradient = output_gradient(&output);
+        assert_eq!(
+            activation_gradient.len(),
+            output.len(),
+            "Invalid output gradient len for fcn"
+        );
+        activations.push(output);
+        let mut gradient = Array1::zeros(params.len());
+        for i in (1..self.layers.len()).rev() {
+            let activation = &self.layers[i].1;
+            let delta = activation_gradient * &activation.derivative_all(&pre_activations[i]);
+            let (matrix, bias) = self.weights_and_bias(params, i, params_offsets[i - 1]);
+            let matrix_gradient = delta
+                .view()
+                .insert_axis(Axis(1))
+                .dot(&activations[i - 1].view().insert_axis(Axis(0)));
+            let bias_offset = params_offsets[i - 1] + matrix.len();
+            gradient
+                .slice_mut(s![params_offsets[i - 1]..bias_offset])
+                .into_shape(matrix.dim())
+                .unwrap()
+                .assign(&matrix_gradient);
+            gradient
+                .slice_mut(s![bias_offset..(bias_offset + bias.len())])
+                .assign(&delta);
+            activation_gradient = matrix.t().dot(&delta);
+        }
+        (activations.pop().unwrap(), gradient)
     }
 
     pub fn at(&self, input: &Array1<f32>) -> Array1<f32> {
-        self.at_with(&input, &self.params)
+        self.at_with(input, &self.params)
+    }
+
+    pub fn at_batch(&self, inputs: &Array2<f32>) -> Array2<f32> {
+        self.at_batch_with(inputs, &self.params)
+    }
+}
+
+/// An FCN along with views of the weight matrices and biases of a params vector.
+pub struct BoundFCN<'a> {
+    layers: &'a [(usize, Activation)],
+    weights_and_biases: Vec<(ArrayView2<'a, f32>, ArrayView1<'a, f32>)>,
+}
+
+impl<'a> BoundFCN<'a> {
+    pub fn at(&self, input: &Array1<f32>) -> Array1<f32> {
+        assert_eq!(input.len(), self.layers[0].0, "Invalid input len for fcn");
+        let activation = &self.layers[0].1;
+        let mut output = activation.apply_all(input);
+        for ((matrix, bias), (_, activation)) in
+            self.weights_and_biases.iter().zip(&self.layers[1..])
+        {
+            output = activation.apply_all(&(matrix.dot(&output) + bias));
+        }
+        output
+    }
+
+    /// Each row of inputs is an input, each row of the result its output.
+    pub fn at_batch(&self, inputs: &Array2<f32>) -> Array2<f32> {
+        assert_eq!(
+            inputs.ncols(),
+            self.layers[0].0,
+            "Invalid input len for fcn"
+        );
+        let activation = &self.layers[0].1;
+        let mut outputs = activation.apply_rows(inputs);
+        for ((matrix, bias), (_, activation)) in
+            self.weights_and_biases.iter().zip(&self.layers[1..])
+        {
+            outputs = activation.apply_rows(&(outputs.dot(&matrix.t()) + bias));
+        }
+        outputs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::seeded_rng;
+
+    #[test]
+    fn backward_matches_finite_differences() {
+        let fcn = FCN::new(
+            vec![
+                (3, Activation::Linear),
+                (4, Activation::Tanh),
+                (4, Activation::LeakyReLu(0.1)),
+                (4, Activation::Softplus),
+                (4, Activation::Sigmoid(-0.5, 1.5)),
+                (4, Activation::ScaledSigmoid(-0.5, 1.5)),
+                (2, Activation::Bounded(vec![(-1.0, 2.0), (0.0, 0.5)])),
+            ],
+            Initializer::HeNormal,
+            &mut seeded_rng(0),
+        );
+        let input = array![0.3, -0.7, 1.1];
+        let weights = array![0.8, -1.3];
+        let (output, analytic) = fcn.backward_with(&input, fcn.params(), |_| weights.clone());
+        assert_eq!(output, fcn.at(&input));
+        let h = 1e-2;
+        for j in 0..fcn.params().len() {
+            let (mut params_plus, mut params_minus) = (fcn.params().clone(), fcn.params().clone());
+            params_plus[j] += h;
+            params_minus[j] -= h;
+            let numeric = (fcn.at_with(&input, &params_plus).dot(&weights)
+                - fcn.at_with(&input, &params_minus).dot(&weights))
+                / (2.0 * h);
+            assert!(
+                (analytic[j] - numeric).abs() < 1e-3,
+                "d output/d params[{}]: analytic {} vs numeric {}",
+                j,
+                analytic[j],
+                numeric
+            );
+        }
+    }
+
+    #[test]
+    fn batched_and_bound_match_row_by_row() {
+        let fcn = FCN::new(
+            vec![
+                (3, Activation::Linear),
+                (5, Activation::ReLu),
+                (5, Activation::Elu(0.5)),
+                (4, Activation::LeakyReLu(0.1)),
+                (4, Activation::Sigmoid(-0.5, 1.5)),
+                (4, Activation::Tanh),
+                (2, Activation::Bounded(vec![(-1.0, 2.0), (0.0, 0.5)])),
+            ],
+            Initializer::HeNormal,
+            &mut seeded_rng(0),
+        );
+        let inputs = Array2::from_shape_fn((6, 3), |(i, j)| (i as f32 - 2.5) * (j as f32 + 0.5));
+        let other_params = fcn.params().mapv(|p| 0.5 - p);
+        let assert_close = |a: ArrayView1<f32>, b: &Array1<f32>| {
+            for (x, y) in a.iter().zip(b) {
+                assert!((x - y).abs() < 1e-5, "{} vs {}", a, b);
+            }
+        };
+        let bound = fcn.bind(&other_params);
+        let (batch, batch_with, bound_batch) = (
+            fcn.at_batch(&inputs),
+            fcn.at_batch_with(&inputs, &other_params),
+            bound.at_batch(&inputs),
+        );
+        for (i, input) in inputs.outer_iter().enumerate() {
+            let input = input.to_owned();
+            let expected = fcn.at_with(&input, fcn.params());
+            assert_close(batch.row(i), &expected);
+            assert_close(fcn.at(&input).view(), &expected);
+            let expected = fcn.at_with(&input, &other_params);
+            assert_close(batch_with.row(i), &expected);
+            assert_close(bound_batch.row(i), &expected);
+            assert_close(bound.at(&input).view(), &expected);
+        }
+    }
+
+    /// A 2 - 3 - 1 fcn as saved before initializers and the newer activations, hidden pre
+    /// activations (1, -2, 0.5) and output pre activation the sum of the hidden activations.
+    const LEGACY_JSON: &str = r#"{
+        "layers": [[2, "Linear"], [3, {"LeakyReLu": 0.1}], [1, {"Sigmoid": [-0.5, 1.5]}]],
+        "params": {
+            "v": 1,
+            "dim": [13],
+            "data": [0, 0, 0, 0, 0, 0, 1, -2, 0.5, 1, 1, 1, 0]
+        }
+    }"#;
+
+    #[test]
+    fn legacy_json_keeps_legacy_sigmoid() {
+        let fcn: FCN = serde_json::from_str(LEGACY_JSON).unwrap();
+        let pre_activation: f32 = 1.0 - 0.2 + 0.5;
+        let legacy = (sigmoid(pre_activation) + 0.5) / 2.0;
+        assert_eq!(fcn.at(&array![0.3, -0.7]), array![legacy]);
+        // Not scaled into (min, max)
+        let scaled = Activation::ScaledSigmoid(-0.5, 1.5).apply(0, pre_activation);
+        assert!((legacy - scaled).abs() > 0.1);
+    }
+
+    #[test]
+    fn load_checks_layers_and_params_len() {
+        let bad_bounded_len = LEGACY_JSON.replace(
+            r#"{"Sigmoid": [-0.5, 1.5]}"#,
+            r#"{"Bounded": [[-1.0, 1.0], [0.0, 1.0]]}"#,
+        );
+        let error = serde_json::from_str::<FCN>(&bad_bounded_len).unwrap_err();
+        assert!(error.to_string().contains("Bad bounded arguments len."));
+        let bad_sigmoid = LEGACY_JSON.replace("[-0.5, 1.5]", "[1.5, -0.5]");
+        let error = serde_json::from_str::<FCN>(&bad_sigmoid).unwrap_err();
+        assert!(error.to_string().contains("Bad sigmoid arguments."));
+        let bad_params_len = LEGACY_JSON
+            .replace("[13]", "[12]")
+            .replace(", 1, 0]", ", 1]");
+        let error = serde_json::from_str::<FCN>(&bad_params_len).unwrap_err();
+        assert!(error.to_string().contains("Invalid params len for fcn"));
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        let fcn = FCN::new(
+            vec![
+                (3, Activation::Linear),
+                (4, Activation::ScaledSigmoid(-1.0, 1.0)),
+                (2, Activation::Bounded(vec![(-1.0, 2.0), (0.0, 0.5)])),
+            ],
+            Initializer::XavierUniform,
+            &mut seeded_rng(0),
+        );
+        let loaded: FCN = serde_json::from_str(&serde_json::to_string(&fcn).unwrap()).unwrap();
+        assert_eq!(loaded.params(), fcn.params());
+        let input = array![0.3, -0.7, 1.1];
+        assert_eq!(loaded.at(&input), fcn.at(&input));
+    }
+
+    fn layers() -> Vec<(usize, Activation)> {
+        vec![
+            (3, Activation::Linear),
+            (4, Activation::Tanh),
+            (2, Activation::Linear),
+        ]
+    }
+
+    #[test]
+    fn zeros_initializer() {
+        let fcn = FCN::new(layers(), Initializer::Zeros, &mut seeded_rng(0));
+        assert_eq!(fcn.params().len(), (3 + 1) * 4 + (4 + 1) * 2);
+        assert!(fcn.params().iter().all(|&p| p == 0.0));
+    }
+
+    #[test]
+    fn params_initializer_copies_another_fcn() {
+        let trained = FCN::new(layers(), Initializer::HeNormal, &mut seeded_rng(0));
+        let fcn = FCN::new(
+            layers(),
+            Initializer::from_fcn(&trained),
+            &mut seeded_rng(1),
+        );
+        assert_eq!(fcn.params(), trained.params());
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid params len for initializer")]
+    fn params_initializer_rejects_other_len() {
+        let trained = FCN::new(layers(), Initializer::HeNormal, &mut seeded_rng(0));
+        FCN::new(
+            vec![(3, Activation::Linear), (2, Activation::Linear)],
+            Initializer::from_fcn(&trained),
+            &mut seeded_rng(0),
+        );
+    }
+
+    #[test]
+    fn missing_initializer_loads_as_legacy_uniform() {
+        let fcn: FCN = serde_json::from_str(LEGACY_JSON).unwrap();
+        match fcn.initializer() {
+            Initializer::Uniform(min, max) => assert_eq!((*min, *max), (0.0, 1.0)),
+            initializer => panic!("Unexpected initializer {:?}", initializer),
+        }
     }
 }
@@ -1,5 +1,14 @@
 pub mod ceo;
+pub mod cma_es;
+pub mod elman;
+pub mod encoding;
 pub mod experiment;
 pub mod fcn;
+pub mod policy;
+pub mod reach;
+pub mod route;
+pub mod supervised;
+pub mod sweep;
+pub mod telemetry;
 pub mod utils;
 pub mod world;
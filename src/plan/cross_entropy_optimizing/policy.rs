@@ -0,0 +1,67 @@
+use super::fcn::{BoundFCN, FCN};
+use ndarray::prelude::*;
+
+/// A parametrized map from inputs to outputs, with hidden state carried across steps of an episode.
+/// The params are a flat vector, so that black box optimizers can perturb them freely.
+pub trait Policy: Sync {
+    fn params(&self) -> &Array1<f32>;
+
+    fn set_params(&mut self, new_params: Array1<f32>);
+
+    /// Hidden state at the start of an episode, empty for stateless policies.
+    fn initial_state(&self) -> Array1<f32>;
+
+    /// Parses params once, for all the steps of many episodes.
+    fn bind<'a>(&'a self, params: &'a Array1<f32>) -> Box<dyn BoundPolicy + 'a>;
+
+    /// Output for input, advancing state.
+    fn step_with(
+        &self,
+        input: &Array1<f32>,
+        state: &mut Array1<f32>,
+        params: &Array1<f32>,
+    ) -> Array1<f32> {
+        self.bind(params).step(input, state)
+    }
+
+    fn step(&self, input: &Array1<f32>, state: &mut Array1<f32>) -> Array1<f32> {
+        self.step_with(input, state, self.params())
+    }
+}
+
+/// A policy along with the params it was bound to, see `Policy::bind`.
+pub trait BoundPolicy: Sync {
+    /// Hidden state at the start of an episode, empty for stateless policies.
+    fn initial_state(&self) -> Array1<f32>;
+
+    /// Output for input, advancing state.
+    fn step(&self, input: &Array1<f32>, state: &mut Array1<f32>) -> Array1<f32>;
+}
+
+impl Policy for FCN {
+    fn params(&self) -> &Array1<f32> {
+        FCN::params(self)
+    }
+
+    fn set_params(&mut self, new_params: Array1<f32>) {
+        FCN::set_params(self, new_params)
+    }
+
+    fn initial_state(&self) -> Array1<f32> {
+        Array1::zeros(0)
+    }
+
+    fn bind<'a>(&'a self, params: &'a Array1<f32>) -> Box<dyn BoundPolicy + 'a> {
+        Box::new(FCN::bind(self, params))
+    }
+}
+
+impl<'a> BoundPolicy for BoundFCN<'a> {
+    fn initial_state(&self) -> Array1<f32> {
+        Array1::zeros(0)
+    }
+
+    fn step(&self, input: &Array1<f32>, _state: &mut Array1<f32>) -> Array1<f32> {
+        self.at(input)
+    }
+}
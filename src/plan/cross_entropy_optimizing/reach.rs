@@ -0,0 +1,362 @@
+use super::ceo::Reward;
+use super::encoding::{Decoder, Encoder};
+use super::policy::{BoundPolicy, Policy};
+use super::utils::{control, random_sample_solve, GoalQsCouple};
+use super::world::World;
+use crate::act::one_holding_switchable_nr_couple::OneHoldingSwitchableNRCouple;
+use crate::act::switchable_nr::SwitchableNR;
+use crate::game::goal_couple_plugin::GoalCouple;
+use crate::plan::{derive_seed, seeded_rng};
+use ndarray::prelude::*;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::num::NonZeroUsize;
+
+/// Why the non holding end never got within slack of its goal.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ReachFailure {
+    /// Farther from the holding origin than the total length of both chains.
+    OutOfReach,
+    /// The holding end never got within slack of the planned holding goal either.
+    HoldingGoalMissed,
+    /// The holding end got to its goal, the non holding end did not.
+    NonHoldingGoalMissed,
+}
+
+/// Distances and com heights are divided by the total length of both chains, com heights are
+/// relative to the holding origin.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReachEpisode {
+    pub reward: f32,
+    /// Ticks until the non holding end first got within slack of its goal.
+    pub reached_at: Option<usize>,
+    /// The non holding end ended the episode within slack of its goal.
+    pub success: bool,
+    pub final_distance: f32,
+    pub final_com_height: f32,
+    pub failure: Option<ReachFailure>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Percentiles {
+    pub mean: f32,
+    pub min: f32,
+    pub p10: f32,
+    pub p50: f32,
+    pub p90: f32,
+    pub max: f32,
+}
+
+impl Percentiles {
+    /// Nearest rank percentiles; None for no values.
+    pub fn of(values: &[f32]) -> Option<Percentiles> {
+        if values.is_empty() {
+            return None;
+        }
+        let mut sorted = values.to_vec();
+        sorted.sort_by(f32::total_cmp);
+        let rank =
+            |p: f32| sorted[((p * sorted.len() as f32).ceil() as usize).clamp(1, sorted.len()) - 1];
+        Some(Percentiles {
+            mean: sorted.iter().sum::<f32>() / sorted.len() as f32,
+            min: sorted[0],
+            p10: rank(0.1),
+            p50: rank(0.5),
+            p90: rank(0.9),
+            max: sorted[sorted.len() - 1],
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FailureCounts {
+    pub out_of_reach: usize,
+    pub holding_goal_missed: usize,
+    pub non_holding_goal_missed: usize,
+}
+
+/// Statistics of a policy over episodes, see `ReachReward::evaluate`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReachEvaluation {
+    pub num_episodes: usize,
+    pub num_episode_ticks: usize,
+    pub seed: u64,
+    pub slack: f32,
+    /// Episodes that ended within slack of the goal.
+    pub success_rate: f32,
+    /// Episodes that got within slack of the goal at some tick, even if they left it after.
+    pub reached_rate: f32,
+    pub mean_reward: f32,
+    /// Over the episodes that reached the goal only.
+    pub time_to_reach: Option<Percentiles>,
+    pub final_com_height: Option<Percentiles>,
+    pub failures: FailureCounts,
+    pub episodes: Vec<ReachEpisode>,
+}
+
+/// Reaching a goal sampled from the world with the non holding end. The policy is queried at the
+/// start of each episode and, if replan_every is set, again every that many ticks, carrying its
+/// hidden state across queries.
+pub struct ReachReward {
+    pub world: World,
+    pub encoder: Encoder,
+    pub decoder: Decoder,
+    pub replan_every: Option<NonZeroUsize>,
+}
+
+impl ReachReward {
+    /// The goals count as reached within slack, which only affects the statistics; the reward
+    /// always uses `SwitchableNR::GOAL_REACHED_SLACK`.
+    pub fn episode(
+        &self,
+        policy: &dyn BoundPolicy,
+        num_episode_ticks: usize,
+        episode_seed: u64,
+        slack: f32,
+    ) -> ReachEpisode {
+        let mut rng = seeded_rng(episode_seed);
+        // Spawn agent
+        let mut agent = OneHoldingSwitchableNRCouple::new(
+            &self.world.holding_side,
+            self.world.origin,
+            &self.world.holding_ls,
+            &self.world.sample_holding_qs(&mut rng),
+            &self.world.holding_q_clamps(),
+            self.world.holding_links.as_deref(),
+            self.world.holding_joint_limits.clone(),
+            &self.world.non_holding_ls,
+            &self.world.sample_non_holding_qs(&mut rng),
+            &self.world.non_holding_q_clamps(),
+            self.world.non_holding_links.as_deref(),
+            self.world.non_holding_joint_limits.clone(),
+        );
+        let holding_origin = *agent.holding().get_current_state().1;
+        let non_holding_goal = self.world.sample_goal(&mut rng);
+        let mut state = policy.initial_state();
+        let mut goal_couple = GoalCouple(holding_origin, non_holding_goal);
+        let mut goal_qs_couple = GoalQsCouple(Array::zeros(0), Array::zeros(0));
+        let mut ticks_since_plan = 0;
+        let mut holding_reached_at = None;
+        let mut reached_at = None;
+        // Start calculating reward
+        let mut episode_reward = 0.0;
+        for ticks in 0..num_episode_ticks {
+            let replan = match self.replan_every {
+                Some(replan_every) => ticks % replan_every.get() == 0,
+                None => ticks == 0,
+            };
+            if replan {
+                // Network pipeline
+                let (input, scale) = self.encoder.encode(&agent, &non_holding_goal, &[]);
+                let forward_pass = policy.step(&input, &mut state);
+                let holding_goal = self.decoder.decode(&forward_pass, scale, &agent);
+                // Setting GoalCouple and GoalQsCouple
+                goal_couple = GoalCouple(holding_goal, non_holding_goal);
+                random_sample_solve(
+                    &agent,
+                    &goal_couple,
+                    &mut goal_qs_couple,
+                    derive_seed(episode_seed, ticks as u64),
+                );
+                ticks_since_plan = 0;
+            }
+            let holding_goal = goal_couple.0;
+            // Apply control
+            control(&mut agent, &goal_qs_couple, &goal_couple, ticks_since_plan);
+            ticks_since_plan += 1;
+            // Holding
+            let last_vertex = agent.holding().get_last_vertex();
+            let dist = (last_vertex - holding_goal).length();
+            episode_reward -= 2.0 * dist;
+            if dist < slack && holding_reached_at.is_none() {
+                holding_reached_at = Some(ticks + 1);
+            }
+            // Non holding
+            let last_vertex = agent.non_holding().get_last_vertex();
+            let dist = (last_vertex - non_holding_goal).length();
+            episode_reward -= 10.0 * dist;
+            if dist < slack && reached_at.is_none() {
+                reached_at = Some(ticks + 1);
+            }
+            // COM y
+            let com = agent.get_center_of_mass();
+            episode_reward -= 5.0 * com[1];
+            // COM x
+            let com = agent.get_center_of_mass();
+            episode_reward -= (com[0] - (non_holding_goal[0] + holding_origin[0]) / 2.0).abs();
+        }
+        // Holding
+        let holding_goal = goal_couple.0;
+        let last_vertex = agent.holding().get_last_vertex();
+        let dist = (last_vertex - holding_goal).length();
+        if dist < SwitchableNR::GOAL_REACHED_SLACK {
+            episode_reward += 500.0;
+        }
+        // Non holding
+        let last_vertex = agent.non_holding().get_last_vertex();
+        let final_distance = (last_vertex - non_holding_goal).length();
+        if final_distance < SwitchableNR::GOAL_REACHED_SLACK {
+            episode_reward += 1000.0;
+        }
+        let scale = self.world.holding_ls.iter().sum::<f32>()
+            + self.world.non_holding_ls.iter().sum::<f32>();
+        let failure = if reached_at.is_some() {
+            None
+        } else if (non_holding_goal - holding_origin).length() > scale {
+            Some(ReachFailure::OutOfReach)
+        } else if holding_reached_at.is_none() {
+            Some(ReachFailure::HoldingGoalMissed)
+        } else {
+            Some(ReachFailure::NonHoldingGoalMissed)
+        };
+        ReachEpisode {
+            reward: episode_reward,
+            reached_at,
+            success: final_distance < slack,
+            final_distance: final_distance / scale,
+            final_com_height: (agent.get_center_of_mass()[1] - holding_origin[1]) / scale,
+            failure,
+        }
+    }
+
+    /// Runs the episodes of `Reward::average_reward` with the policy's own params, in parallel.
+    pub fn evaluate<P: Policy>(
+        &self,
+        policy: &P,
+        num_episodes: usize,
+        num_episode_ticks: usize,
+        seed: u64,
+        slack: f32,
+    ) -> ReachEvaluation {
+        assert!(num_episodes > 0, "Trying to evaluate on no episodes.");
+        let policy = policy.bind(policy.params());
+        let episodes = (0..num_episodes)
+            .into_par_iter()
+            .map(|episode| {
+                self.episode(
+                    policy.as_ref(),
+                    num_episode_ticks,
+                    derive_seed(seed, episode as u64),
+                    slack,
+                )
+            })
+            .collect::<Vec<ReachEpisode>>();
+        let times_to_reach = episodes
+            .iter()
+            .filter_map(|episode| episode.reached_at.map(|ticks| ticks as f32))
+            .collect::<Vec<f32>>();
+        let final_com_heights = episodes
+            .iter()
+            .map(|episode| episode.final_com_height)
+            .collect::<Vec<f32>>();
+        let mut failures = FailureCounts::default();
+        for episode in &episodes {
+            match episode.failure {
+                Some(ReachFailure::OutOfReach) => failures.out_of_reach += 1,
+                Some(ReachFailure::HoldingGoalMissed) => failures.holding_goal_missed += 1,
+                Some(ReachFailure::NonHoldingGoalMissed) => failures.non_holding_goal_missed += 1,
+                None => {}
+            }
+        }
+        ReachEvaluation {
+            num_episodes,
+            num_episode_ticks,
+            seed,
+            slack,
+            success_rate: episodes.iter().filter(|episode| episode.success).count() as f32
+                / num_episodes as f32,
+            reached_rate: times_to_reach.len() as f32 / num_episodes as f32,
+            mean_reward: episodes.iter().map(|episode| episode.reward).sum::<f32>()
+                / num_episodes as f32,
+            time_to_reach: Percentiles::of(&times_to_reach),
+            final_com_height: Percentiles::of(&final_com_heights),
+            failures,
+            episodes,
+        }
+    }
+}
+
+impl<P: Policy> Reward<P> for ReachReward {
+    fn episode_reward(
+        &self,
+        policy: &dyn BoundPolicy,
+        num_episode_ticks: usize,
+        episode_seed: u64,
+    ) -> f32 {
+        self.episode(
+            policy,
+            num_episode_ticks,
+            episode_seed,
+            SwitchableNR::GOAL_REACHED_SLACK,
+        )
+        .reward
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::act::switchable_nr::Side;
+    use crate::plan::cross_entropy_optimizing::fcn::{Activation, Initializer, FCN};
+    use bevy::prelude::Vec2;
+
+    #[test]
+    fn nearest_rank_percentiles() {
+        assert!(Percentiles::of(&[]).is_none());
+        let percentiles =
+            Percentiles::of(&[7.0, 3.0, 10.0, 1.0, 5.0, 2.0, 9.0, 4.0, 8.0, 6.0]).unwrap();
+        assert_eq!(
+            (
+                percentiles.mean,
+                percentiles.min,
+                percentiles.p10,
+                percentiles.p50,
+                percentiles.p90,
+                percentiles.max
+            ),
+            (5.5, 1.0, 1.0, 5.0, 9.0, 10.0)
+        );
+        let percentiles = Percentiles::of(&[3.0, 1.0, 2.0]).unwrap();
+        assert_eq!(
+            (percentiles.p10, percentiles.p50, percentiles.p90),
+            (1.0, 2.0, 3.0)
+        );
+        // A diverged value sorts last instead of panicking
+        let percentiles = Percentiles::of(&[f32::NAN, 1.0]).unwrap();
+        assert_eq!(percentiles.min, 1.0);
+        assert!(percentiles.max.is_nan());
+    }
+
+    #[test]
+    fn evaluate_is_deterministic() {
+        let pi = std::f32::consts::PI;
+        let reward = ReachReward {
+            world: World {
+                holding_side: Side::Left,
+                origin: Vec2::new(0.0, 0.0),
+                holding_ls: vec![0.2, 0.2],
+                holding_q_clamps: vec![(None, None), (Some(-pi), Some(-0.0))],
+                holding_links: None,
+                holding_joint_limits: None,
+                non_holding_ls: vec![0.2, 0.2],
+                non_holding_q_clamps: vec![(None, None), (Some(-pi), Some(-0.0))],
+                non_holding_links: None,
+                non_holding_joint_limits: None,
+                unscaled_relative_goal_region: (Vec2::new(-0.8, -0.8), Vec2::new(0.1, 0.8)),
+            },
+            encoder: Encoder::default(),
+            decoder: Decoder::RelativeGoal,
+            replan_every: None,
+        };
+        let fcn = FCN::new(
+            vec![
+                (reward.encoder.len(2, 2), Activation::Linear),
+                (2, Activation::Tanh),
+            ],
+            Initializer::XavierUniform,
+            &mut seeded_rng(0),
+        );
+        let evaluate = || serde_json::to_string(&reward.evaluate(&fcn, 2, 5, 11, 0.05)).unwrap();
+        assert_eq!(evaluate(), evaluate());
+    }
+}
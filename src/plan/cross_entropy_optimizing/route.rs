@@ -0,0 +1,310 @@
+use super::ceo::Reward;
+use super::encoding::{Decoder, Encoder};
+use super::policy::{BoundPolicy, Policy};
+use super::utils::{control, random_sample_solve, GoalQsCouple};
+use super::world::World;
+use crate::act::one_holding_switchable_nr_couple::OneHoldingSwitchableNRCouple;
+use crate::act::switchable_nr::{Side, SwitchableNR};
+use crate::game::goal_couple_plugin::GoalCouple;
+use crate::plan::{derive_seed, seeded_rng};
+use bevy::prelude::*;
+use ndarray::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::LinkedList;
+use std::num::NonZeroUsize;
+
+/// Weights of the route reward terms.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteScoring {
+    /// Bonus per hold of the route reached; matching hands does not count.
+    pub hold_reached: f32,
+    /// Penalty per tick spent, until the route is done or failed.
+    pub tick: f32,
+    /// Penalty per tick, times the com height.
+    pub com_height: f32,
+    /// Penalty for failing a reach.
+    pub failure: f32,
+}
+
+impl Default for RouteScoring {
+    fn default() -> RouteScoring {
+        RouteScoring {
+            hold_reached: 1000.0,
+            tick: 1.0,
+            com_height: 5.0,
+            failure: 1000.0,
+        }
+    }
+}
+
+/// How an episode ended.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RouteOutcome {
+    Completed,
+    /// A single reach took longer than max_reach_ticks.
+    ReachTimeout,
+    /// The episode ran out of ticks.
+    OutOfTicks,
+}
+
+/// Com heights are the y of the center of mass above the holding origin, divided by the total
+/// length of both chains.
+#[derive(Debug, Clone, Serialize)]
+pub struct RouteEpisode {
+    pub reward: f32,
+    pub holds_reached: usize,
+    pub ticks: usize,
+    pub mean_com_height: f32,
+    pub outcome: RouteOutcome,
+}
+
+/// Climbs a whole route per episode, as `examples/route_ohc.rs` does: the policy sets the goal
+/// couple whenever the non holding goal changes (and every replan_every ticks, if set), the
+/// hands are matched when the next hold is behind the holding side, and the hold is switched
+/// once the non holding end reaches its goal.
+/// One policy drives both holding sides; give the encoder `Feature::PivotSide` to tell them apart.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteReward {
+    pub world: World,
+    /// Holds in world coordinates; each episode climbs one, picked uniformly at random.
+    pub routes: Vec<Vec<Vec2>>,
+    #[serde(default)]
+    pub encoder: Encoder,
+    #[serde(default)]
+    pub decoder: Decoder,
+    pub max_reach_ticks: usize,
+    #[serde(default)]
+    pub replan_every: Option<NonZeroUsize>,
+    #[serde(default)]
+    pub scoring: RouteScoring,
+}
+
+impl RouteReward {
+    pub fn episode(
+        &self,
+        policy: &dyn BoundPolicy,
+        num_episode_ticks: usize,
+        episode_seed: u64,
+    ) -> RouteEpisode {
+        assert!(!self.routes.is_empty(), "Trying to climb no routes.");
+        assert!(
+            self.max_reach_ticks > 0,
+            "Max reach ticks must be positive."
+        );
+        let mut rng = seeded_rng(episode_seed);
+        // Spawn agent
+        let mut agent = OneHoldingSwitchableNRCouple::new(
+            &self.world.holding_side,
+            self.world.origin,
+            &self.world.holding_ls,
+            &self.world.sample_holding_qs(&mut rng),
+            &self.world.holding_q_clamps(),
+            self.world.holding_links.as_deref(),
+            self.world.holding_joint_limits.clone(),
+            &self.world.non_holding_ls,
+            &self.world.sample_non_holding_qs(&mut rng),
+            &self.world.non_holding_q_clamps(),
+            self.world.non_holding_links.as_deref(),
+            self.world.non_holding_joint_limits.clone(),
+        );
+        let mut path = self.routes[rng.gen_range(0, self.routes.len())]
+            .iter()
+            .copied()
+            .collect::<LinkedList<Vec2>>();
+        let scale = self.world.holding_ls.iter().sum::<f32>()
+            + self.world.non_holding_ls.iter().sum::<f32>();
+        let mut state = policy.initial_state();
+        let mut goal_couple = GoalCouple(Vec2::ZERO, Vec2::ZERO);
+        let mut goal_qs_couple = GoalQsCouple(Array::zeros(0), Array::zeros(0));
+        let mut plan = |agent: &OneHoldingSwitchableNRCouple,
+                        path: &LinkedList<Vec2>,
+                        goal_couple: &mut GoalCouple,
+                        goal_qs_couple: &mut GoalQsCouple,
+                        seed: u64| {
+            let non_holding_goal = *path.front().unwrap();
+            let next_holds = path.iter().skip(1).copied().collect::<Vec<Vec2>>();
+            // Network pipeline
+            let (input, scale) = self.encoder.encode(agent, &non_holding_goal, &next_holds);
+            let forward_pass = policy.step(&input, &mut state);
+            let holding_goal = self.decoder.decode(&forward_pass, scale, agent);
+            // Setting GoalCouple and GoalQsCouple
+            *goal_couple = GoalCouple(holding_goal, non_holding_goal);
+            random_sample_solve(agent, goal_couple, goal_qs_couple, seed);
+        };
+        let mut reward = 0.0;
+        let mut holds_reached = 0;
+        let mut cumulative_com_height = 0.0;
+        let mut reach_ticks = 0;
+        let mut matching = false;
+        let mut outcome = RouteOutcome::OutOfTicks;
+        let mut ticks = 0;
+        while ticks < num_episode_ticks {
+            if path.is_empty() {
+                outcome = RouteOutcome::Completed;
+                break;
+            }
+            if reach_ticks > self.max_reach_ticks {
+                outcome = RouteOutcome::ReachTimeout;
+                reward -= self.scoring.failure;
+                break;
+            }
+            let seed = derive_seed(episode_seed, ticks as u64);
+            if reach_ticks == 0 {
+                let (_, holding_origin, _, _, _, holding_side) =
+                    agent.holding().get_current_state();
+                let non_holding_goal = *path.front().unwrap();
+                let have_to_match = match holding_side {
+                    Side::Left => {
+                        non_holding_goal[0] - holding_origin[0] < -SwitchableNR::GOAL_REACHED_SLACK
+                    }
+                    Side::Right => {
+                        non_holding_goal[0] - holding_origin[0] > SwitchableNR::GOAL_REACHED_SLACK
+                    }
+                };
+                if have_to_match {
+                    // Add current holding origin as goal
+                    path.push_front(*holding_origin);
+                }
+                matching = have_to_match;
+                plan(&agent, &path, &mut goal_couple, &mut goal_qs_couple, seed);
+            } else if let Some(replan_every) = self.replan_every {
+                if reach_ticks % replan_every.get() == 0 {
+                    plan(&agent, &path, &mut goal_couple, &mut goal_qs_couple, seed);
+                }
+            }
+            control(&mut agent, &goal_qs_couple, &goal_couple, reach_ticks);
+            reach_ticks += 1;
+            ticks += 1;
+            let com_height =
+                (agent.get_center_of_mass()[1] - agent.holding().get_current_state().1[1]) / scale;
+            cumulative_com_height += com_height;
+            reward -= self.scoring.tick + self.scoring.com_height * com_height;
+            let non_holding_last = agent.non_holding().get_last_vertex();
+            if (goal_couple.1 - non_holding_last).length() < SwitchableNR::GOAL_REACHED_SLACK {
+                // Switch pivot
+                agent.switch_hold();
+                // Remove one vertex from path
+                path.pop_front();
+                if !matching {
+                    holds_reached += 1;
+                    reward += self.scoring.hold_reached;
+                }
+                reach_ticks = 0;
+            }
+        }
+        if ticks == num_episode_ticks && path.is_empty() {
+            outcome = RouteOutcome::Completed;
+        }
+        RouteEpisode {
+            reward,
+            holds_reached,
+            ticks,
+            mean_com_height: cumulative_com_height / ticks.max(1) as f32,
+            outcome,
+        }
+    }
+}
+
+impl<P: Policy> Reward<P> for RouteReward {
+    fn episode_reward(
+        &self,
+        policy: &dyn BoundPolicy,
+        num_episode_ticks: usize,
+        episode_seed: u64,
+    ) -> f32 {
+        self.episode(policy, num_episode_ticks, episode_seed).reward
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::cross_entropy_optimizing::fcn::{Activation, Initializer, FCN};
+    use crate::plan::seeded_rng;
+
+    /// Small enough that every hold within reach is reached on the first tick of its reach.
+    fn route_reward(route: Vec<Vec2>, replan_every: Option<NonZeroUsize>) -> RouteReward {
+        let pi = std::f32::consts::PI;
+        RouteReward {
+            world: World {
+                holding_side: Side::Left,
+                origin: Vec2::new(0.0, 0.0),
+                holding_ls: vec![0.2, 0.2],
+                holding_q_clamps: vec![(None, None), (Some(-pi), Some(-0.0))],
+                holding_links: None,
+                holding_joint_limits: None,
+                non_holding_ls: vec![0.2, 0.2],
+                non_holding_q_clamps: vec![(None, None), (Some(-pi), Some(-0.0))],
+                non_holding_links: None,
+                non_holding_joint_limits: None,
+                unscaled_relative_goal_region: (Vec2::new(-0.8, -0.8), Vec2::new(0.1, 0.8)),
+            },
+            routes: vec![route],
+            encoder: Encoder::default(),
+            decoder: Decoder::EndDisplacement,
+            max_reach_ticks: 3,
+            replan_every,
+            scoring: RouteScoring::default(),
+        }
+    }
+
+    /// Outputs zeros, i.e. keeps the holding end where it is.
+    fn still_fcn(reward: &RouteReward) -> FCN {
+        FCN::new(
+            vec![
+                (
+                    reward.encoder.len(
+                        reward.world.holding_ls.len(),
+                        reward.world.non_holding_ls.len(),
+                    ),
+                    Activation::Linear,
+                ),
+                (reward.decoder.output_dof(), Activation::Linear),
+            ],
+            Initializer::Zeros,
+            &mut seeded_rng(0),
+        )
+    }
+
+    #[test]
+    fn climbs_and_scores_routes() {
+        let route = vec![Vec2::new(0.3, 0.1), Vec2::new(0.6, 0.2)];
+        for replan_every in [None, NonZeroUsize::new(1)] {
+            let reward = route_reward(route.clone(), replan_every);
+            let fcn = still_fcn(&reward);
+            let policy = fcn.bind(fcn.params());
+            let episode = reward.episode(&policy, 100, 0);
+            assert_eq!(episode.outcome, RouteOutcome::Completed);
+            assert_eq!(episode.holds_reached, 2);
+            assert_eq!(episode.ticks, 2);
+            let scoring = &reward.scoring;
+            let expected = 2.0 * scoring.hold_reached
+                - episode.ticks as f32
+                    * (scoring.tick + scoring.com_height * episode.mean_com_height);
+            assert!((episode.reward - expected).abs() < 1e-3);
+            assert_eq!(
+                Reward::<FCN>::average_reward(&reward, &fcn, fcn.params(), 1, 100, 0),
+                reward.episode(&policy, 100, derive_seed(0, 0)).reward
+            );
+        }
+        // Out of reach
+        let reward = route_reward(vec![Vec2::new(50.0, 0.0)], None);
+        let fcn = still_fcn(&reward);
+        let episode = reward.episode(&fcn.bind(fcn.params()), 100, 0);
+        assert_eq!(episode.outcome, RouteOutcome::ReachTimeout);
+        assert_eq!(episode.holds_reached, 0);
+        assert_eq!(episode.ticks, reward.max_reach_ticks + 1);
+        assert!(episode.reward < -reward.scoring.failure);
+    }
+
+    #[test]
+    fn rejects_zero_replan_interval() {
+        let reward = route_reward(vec![Vec2::new(0.3, 0.1)], NonZeroUsize::new(1));
+        let mut json = serde_json::to_value(reward).unwrap();
+        json["replan_every"] = serde_json::json!(0);
+        assert!(serde_json::from_value::<RouteReward>(json).is_err());
+    }
+}
@@ -0,0 +1,189 @@
+use super::fcn::*;
+use crate::plan::{derive_seed, seeded_rng};
+use ndarray::prelude::*;
+use rand::seq::SliceRandom;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum Optimizer {
+    SGD {
+        learning_rate: f32,
+    },
+    Adam {
+        learning_rate: f32,
+        beta1: f32,
+        beta2: f32,
+        epsilon: f32,
+    },
+}
+
+/// Mini batch regression of an FCN onto (input, target) rows, minimizing the mean of
+/// 0.5 * |fcn(input) - target|^2.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Supervised {
+    pub epochs: usize,
+    pub batch_size: usize,
+    pub optimizer: Optimizer,
+    #[serde(default)]
+    pub seed: u64,
+}
+
+impl Default for Supervised {
+    fn default() -> Supervised {
+        Supervised {
+            epochs: 100,
+            batch_size: 32,
+            optimizer: Optimizer::Adam {
+                learning_rate: 1e-3,
+                beta1: 0.9,
+                beta2: 0.999,
+                epsilon: 1e-8,
+            },
+            seed: 0,
+        }
+    }
+}
+
+impl Supervised {
+    /// Returns the mean loss of every epoch.
+    pub fn fit(&self, fcn: &mut FCN, inputs: &Array2<f32>, targets: &Array2<f32>) -> Vec<f32> {
+        assert_eq!(
+            inputs.nrows(),
+            targets.nrows(),
+            "Mismatched number of inputs and targets."
+        );
+        assert!(inputs.nrows() > 0, "Trying to fit on no data.");
+        assert!(self.batch_size > 0, "Batch size must be positive.");
+        let num_params = fcn.params().len();
+        // Adam moments
+        let mut first_moment = Array1::<f32>::zeros(num_params);
+        let mut second_moment = Array1::<f32>::zeros(num_params);
+        let mut step = 0;
+        let mut indices = (0..inputs.nrows()).collect::<Vec<usize>>();
+        let mut epoch_losses = vec![];
+        for epoch in 0..self.epochs {
+            indices.shuffle(&mut seeded_rng(derive_seed(self.seed, epoch as u64)));
+            let mut epoch_loss = 0.0;
+            for batch in indices.chunks(self.batch_size) {
+                // Summed in batch order, so that the result does not depend on the thread count
+                let losses_and_gradients = batch
+                    .par_iter()
+                    .map(|&i| {
+                        let (output, gradient) =
+                            fcn.backward_with(&inputs.row(i).to_owned(), fcn.params(), |output| {
+                                output - &targets.row(i)
+                            });
+                        let error = output - targets.row(i);
+                        (0.5 * error.dot(&error), gradient)
+                    })
+                    .collect::<Vec<_>>();
+                let mut loss = 0.0;
+                let mut gradient = Array1::<f32>::zeros(num_params);
+                for (sample_loss, sample_gradient) in losses_and_gradients {
+                    loss += sample_loss;
+                    gradient += &sample_gradient;
+                }
+                epoch_loss += loss;
+                let gradient = gradient / batch.len() as f32;
+                step += 1;
+                let update = match self.optimizer {
+                    Optimizer::SGD { learning_rate } => learning_rate * gradient,
+                    Optimizer::Adam {
+                        learning_rate,
+                        beta1,
+                        beta2,
+                        epsilon,
+                    } => {
+                        first_moment = beta1 * &first_moment + (1.0 - beta1) * &gradient;
+                        second_moment =
+                            beta2 * &second_moment + (1.0 - beta2) * &gradient.mapv(|g| g * g);
+                        let first_moment_hat = &first_moment / (1.0 - beta1.powi(step));
+                        let second_moment_hat = &second_moment / (1.0 - beta2.powi(step));
+                        learning_rate * first_moment_hat
+                            / second_moment_hat.mapv(|v| v.sqrt() + epsilon)
+                    }
+                };
+                fcn.set_params(fcn.params() - &update);
+            }
+            epoch_losses.push(epoch_loss / inputs.nrows() as f32);
+        }
+        epoch_losses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rows of y = 2 x0 - x1 + 0.5 on a grid.
+    fn linear_data() -> (Array2<f32>, Array2<f32>) {
+        let inputs = Array2::from_shape_fn((16, 2), |(i, j)| {
+            if j == 0 {
+                (i % 4) as f32 / 2.0 - 0.75
+            } else {
+                (i / 4) as f32 / 2.0 - 0.75
+            }
+        });
+        let targets = inputs
+            .outer_iter()
+            .map(|x| 2.0 * x[0] - x[1] + 0.5)
+            .collect::<Array1<f32>>()
+            .into_shape((16, 1))
+            .unwrap();
+        (inputs, targets)
+    }
+
+    fn fit(optimizer: Optimizer) -> (Vec<f32>, Array1<f32>) {
+        let (inputs, targets) = linear_data();
+        let mut fcn = FCN::new(
+            vec![(2, Activation::Linear), (1, Activation::Linear)],
+            Initializer::Zeros,
+            &mut seeded_rng(0),
+        );
+        let supervised = Supervised {
+            epochs: 100,
+            batch_size: 4,
+            optimizer,
+            seed: 7,
+        };
+        let losses = supervised.fit(&mut fcn, &inputs, &targets);
+        (losses, fcn.params().clone())
+    }
+
+    fn optimizers() -> Vec<Optimizer> {
+        vec![
+            Optimizer::SGD { learning_rate: 0.1 },
+            Optimizer::Adam {
+                learning_rate: 0.05,
+                beta1: 0.9,
+                beta2: 0.999,
+                epsilon: 1e-8,
+            },
+        ]
+    }
+
+    #[test]
+    fn optimizers_reduce_loss() {
+        for optimizer in optimizers() {
+            let (losses, _) = fit(optimizer);
+            assert_eq!(losses.len(), 100);
+            assert!(
+                losses[99] < 1e-3 * losses[0],
+                "{:?}: {} -> {}",
+                optimizer,
+                losses[0],
+                losses[99]
+            );
+        }
+    }
+
+    #[test]
+    fn same_seed_same_losses() {
+        for optimizer in optimizers() {
+            assert_eq!(fit(optimizer), fit(optimizer));
+        }
+    }
+}
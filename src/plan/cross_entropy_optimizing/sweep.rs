@@ -0,0 +1,233 @@
+use crate::plan::{derive_seed, seeded_rng};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum Values {
+    /// Each of these, crossed with the values of every other grid parameter.
+    Grid(Vec<Value>),
+    /// One of these per trial, picked uniformly at random.
+    Choice(Vec<Value>),
+    /// Uniform in [min, max) per trial.
+    Uniform(f64, f64),
+    /// Log uniform in [min, max) per trial, for scales like learning rates or stds.
+    LogUniform(f64, f64),
+    /// Uniform integer in [min, max] per trial, for sizes.
+    IntUniform(i64, i64),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Parameter {
+    /// JSON pointer into the base experiment, e.g. "/ceo/elite_frac" or "/policy/layers/1/0".
+    pub pointer: String,
+    pub values: Values,
+}
+
+/// Trials over a base experiment: the grid of all grid parameters, with every grid point repeated
+/// `samples` times, each repetition drawing the random parameters anew.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Sweep {
+    pub parameters: Vec<Parameter>,
+    pub samples: usize,
+    #[serde(default)]
+    pub seed: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Trial {
+    pub index: usize,
+    /// Seeds the random parameters; use it to seed the trial's optimizer and params too.
+    pub seed: u64,
+    /// (pointer, value) of every parameter, in the order of the sweep.
+    pub assignments: Vec<(String, Value)>,
+}
+
+impl Sweep {
+    /// Panics on a spec that some trial could not draw from, so that it fails before any trial
+    /// runs.
+    pub fn validate(&self) {
+        assert!(self.samples > 0, "Sweep samples must be positive.");
+        for parameter in &self.parameters {
+            let pointer = &parameter.pointer;
+            match &parameter.values {
+                Values::Grid(values) => assert!(!values.is_empty(), "Empty grid for {}.", pointer),
+                Values::Choice(values) => {
+                    assert!(!values.is_empty(), "Empty choice for {}.", pointer)
+                }
+                Values::Uniform(min, max) => {
+                    assert!(min < max, "Bad uniform range for {}.", pointer)
+                }
+                Values::LogUniform(min, max) => {
+                    assert!(
+                        *min > 0.0,
+                        "Log uniform range must be positive for {}.",
+                        pointer
+                    );
+                    assert!(min < max, "Bad log uniform range for {}.", pointer)
+                }
+                Values::IntUniform(min, max) => {
+                    assert!(min <= max, "Bad int uniform range for {}.", pointer)
+                }
+            }
+        }
+    }
+
+    pub fn trials(&self) -> Vec<Trial> {
+        self.validate();
+        let mut grid_points: Vec<Vec<Value>> = vec![vec![]];
+        for parameter in &self.parameters {
+            if let Values::Grid(values) = &parameter.values {
+                grid_points = grid_points
+                    .into_iter()
+                    .flat_map(|point| {
+                        values.iter().map(move |value| {
+                            let mut point = point.clone();
+                            point.push(value.clone());
+                            point
+                        })
+                    })
+                    .collect();
+            }
+        }
+        let mut trials = vec![];
+        for grid_point in grid_points {
+            for _ in 0..self.samples {
+                let index = trials.len();
+                let seed = derive_seed(self.seed, index as u64);
+                let mut rng = seeded_rng(seed);
+                let mut grid_values = grid_point.iter();
+                let assignments = self
+                    .parameters
+                    .iter()
+                    .map(|parameter| {
+                        let value = match &parameter.values {
+                            Values::Grid(_) => grid_values.next().unwrap().clone(),
+                            Values::Choice(values) => {
+                                values[rng.gen_range(0, values.len())].clone()
+                            }
+                            Values::Uniform(min, max) => Value::from(rng.gen_range(*min, *max)),
+                            Values::LogUniform(min, max) => {
+                                Value::from(rng.gen_range(min.ln(), max.ln()).exp())
+                            }
+                            Values::IntUniform(min, max) => {
+                                Value::from(rng.gen_range(*min, *max + 1))
+                            }
+                        };
+                        (parameter.pointer.clone(), value)
+                    })
+                    .collect();
+                trials.push(Trial {
+                    index,
+                    seed,
+                    assignments,
+                });
+            }
+        }
+        trials
+    }
+}
+
+impl Trial {
+    /// The base with this trial's values written at their pointers, which must exist in it.
+    pub fn apply(&self, base: &Value) -> Value {
+        let mut experiment = base.clone();
+        for (pointer, value) in &self.assignments {
+            *experiment
+                .pointer_mut(pointer)
+                .unwrap_or_else(|| panic!("No {} in the base experiment.", pointer)) =
+                value.clone();
+        }
+        experiment
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn sweep() -> Sweep {
+        serde_json::from_value(json!({
+            "parameters": [
+                {"pointer": "/ceo/batch_size", "values": {"Grid": [10, 20, 30]}},
+                {"pointer": "/ceo/elite_frac", "values": {"Uniform": [0.1, 0.5]}},
+                {"pointer": "/ceo/seed", "values": {"IntUniform": [4, 4]}},
+                {"pointer": "/world/holding_side", "values": {"Choice": ["Left", "Right"]}},
+                {"pointer": "/ceo/initial_std", "values": {"Grid": [1.0, 2.0]}}
+            ],
+            "samples": 4,
+            "seed": 3
+        }))
+        .unwrap()
+    }
+
+    fn base() -> Value {
+        json!({
+            "ceo": {"batch_size": 50, "elite_frac": 0.25, "seed": 0, "initial_std": 1.0},
+            "world": {"holding_side": "Left"}
+        })
+    }
+
+    #[test]
+    fn trials_cross_grid_and_samples() {
+        let trials = sweep().trials();
+        assert_eq!(trials.len(), 3 * 2 * 4);
+        for (index, trial) in trials.iter().enumerate() {
+            assert_eq!(trial.index, index);
+            let grid_point = index / 4;
+            assert_eq!(trial.assignments[0].1, json!([10, 20, 30][grid_point / 2]));
+            assert_eq!(trial.assignments[4].1, json!([1.0, 2.0][grid_point % 2]));
+            let elite_frac = trial.assignments[1].1.as_f64().unwrap();
+            assert!((0.1..0.5).contains(&elite_frac));
+            assert_eq!(trial.assignments[2].1, json!(4));
+        }
+    }
+
+    #[test]
+    fn same_seed_same_trials() {
+        let (a, b) = (sweep().trials(), sweep().trials());
+        assert_eq!(
+            serde_json::to_value(&a).unwrap(),
+            serde_json::to_value(&b).unwrap()
+        );
+        let other = Sweep { seed: 4, ..sweep() };
+        assert_ne!(
+            serde_json::to_value(&a).unwrap(),
+            serde_json::to_value(other.trials()).unwrap()
+        );
+    }
+
+    #[test]
+    fn apply_writes_through_pointers() {
+        let trial = &sweep().trials()[5];
+        let experiment = trial.apply(&base());
+        for (pointer, value) in &trial.assignments {
+            assert_eq!(experiment.pointer(pointer), Some(value));
+        }
+        // The base is left as is
+        assert_eq!(base()["ceo"]["batch_size"], json!(50));
+    }
+
+    #[test]
+    #[should_panic(expected = "No /ceo/noise in the base experiment.")]
+    fn apply_rejects_unknown_pointer() {
+        let trial = Trial {
+            index: 0,
+            seed: 0,
+            assignments: vec![("/ceo/noise".to_string(), json!(1.0))],
+        };
+        trial.apply(&base());
+    }
+
+    #[test]
+    #[should_panic(expected = "Bad int uniform range for /ceo/seed.")]
+    fn rejects_empty_int_range() {
+        let mut sweep = sweep();
+        sweep.parameters[2].values = Values::IntUniform(5, 4);
+        sweep.trials();
+    }
+}
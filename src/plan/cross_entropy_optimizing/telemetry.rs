@@ -0,0 +1,220 @@
+use serde::Serialize;
+use std::{fs::File, fs::OpenOptions, io, io::Write, path::Path};
+
+/// Statistics of one completed generation of an optimizer.
+#[derive(Debug, Clone, Serialize)]
+pub struct GenerationRecord {
+    /// 1 based.
+    pub generation: usize,
+    pub mean_reward: f32,
+    pub elite_mean_reward: f32,
+    pub best_reward: f32,
+    pub current_params_reward: f32,
+    pub noise_std_mean: f32,
+    pub noise_std_min: f32,
+    pub noise_std_max: f32,
+    /// Seconds since the start of the (possibly resumed) run.
+    pub wall_time: f32,
+}
+
+/// Called once per generation. Returning false stops the optimization after that generation.
+pub trait Observer {
+    fn observe(&mut self, record: &GenerationRecord) -> bool;
+}
+
+impl<F: FnMut(&GenerationRecord) -> bool> Observer for F {
+    fn observe(&mut self, record: &GenerationRecord) -> bool {
+        self(record)
+    }
+}
+
+/// Forwards each record to all observers; continues only if all of them do.
+pub struct Observers(pub Vec<Box<dyn Observer>>);
+
+impl Observer for Observers {
+    fn observe(&mut self, record: &GenerationRecord) -> bool {
+        let mut go_on = true;
+        for observer in self.0.iter_mut() {
+            go_on &= observer.observe(record);
+        }
+        go_on
+    }
+}
+
+/// Opens a telemetry file for a run: a resumed run continues the learning curve, appending to it,
+/// a fresh one starts it over.
+pub fn open(path: &Path, resuming: bool) -> io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resuming)
+        .truncate(!resuming)
+        .open(path)
+}
+
+pub struct Stdout;
+
+impl Observer for Stdout {
+    fn observe(&mut self, record: &GenerationRecord) -> bool {
+        println!(
+            "generation={} mean_reward={:?} reward_with_current_th={:?}, th_std_mean={:?}",
+            record.generation,
+            record.mean_reward,
+            record.current_params_reward,
+            record.noise_std_mean,
+        );
+        true
+    }
+}
+
+pub struct Csv<W: Write> {
+    writer: W,
+    wrote_header: bool,
+}
+
+impl<W: Write> Csv<W> {
+    pub fn new(writer: W) -> Csv<W> {
+        Csv {
+            writer,
+            wrote_header: false,
+        }
+    }
+
+    /// For a writer past the header already, e.g. a file opened to resume a run.
+    pub fn continuing(writer: W) -> Csv<W> {
+        Csv {
+            writer,
+            wrote_header: true,
+        }
+    }
+}
+
+impl<W: Write> Observer for Csv<W> {
+    fn observe(&mut self, record: &GenerationRecord) -> bool {
+        let mut result = Ok(());
+        if !self.wrote_header {
+            result = writeln!(
+                self.writer,
+                "generation,mean_reward,elite_mean_reward,best_reward,current_params_reward,noise_std_mean,noise_std_min,noise_std_max,wall_time"
+            );
+            self.wrote_header = true;
+        }
+        let result = result
+            .and_then(|_| {
+                writeln!(
+                    self.writer,
+                    "{},{},{},{},{},{},{},{},{}",
+                    record.generation,
+                    record.mean_reward,
+                    record.elite_mean_reward,
+                    record.best_reward,
+                    record.current_params_reward,
+                    record.noise_std_mean,
+                    record.noise_std_min,
+                    record.noise_std_max,
+                    record.wall_time,
+                )
+            })
+            .and_then(|_| self.writer.flush());
+        if let Err(e) = result {
+            eprintln!("Failed to write csv telemetry: {}", e);
+        }
+        true
+    }
+}
+
+pub struct JsonLines<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JsonLines<W> {
+    pub fn new(writer: W) -> JsonLines<W> {
+        JsonLines { writer }
+    }
+}
+
+impl<W: Write> Observer for JsonLines<W> {
+    fn observe(&mut self, record: &GenerationRecord) -> bool {
+        let result = serde_json::to_writer(&mut self.writer, record)
+            .map_err(|e| e.into())
+            .and_then(|_| writeln!(self.writer))
+            .and_then(|_| self.writer.flush());
+        if let Err(e) = result {
+            eprintln!("Failed to write json lines telemetry: {}", e);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn record(generation: usize) -> GenerationRecord {
+        GenerationRecord {
+            generation,
+            mean_reward: -1.5,
+            elite_mean_reward: -0.5,
+            best_reward: 0.25,
+            current_params_reward: -0.75,
+            noise_std_mean: 1.0,
+            noise_std_min: 0.5,
+            noise_std_max: 2.0,
+            wall_time: 3.0,
+        }
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("stick_solo_{}_{}", name, std::process::id()))
+    }
+
+    const CSV_HEADER: &str = "generation,mean_reward,elite_mean_reward,best_reward,current_params_reward,noise_std_mean,noise_std_min,noise_std_max,wall_time\n";
+
+    #[test]
+    fn csv_writes_header_once() {
+        let path = temp_path("telemetry.csv");
+        let mut csv = Csv::new(open(&path, false).unwrap());
+        assert!(csv.observe(&record(1)));
+        assert!(csv.observe(&record(2)));
+        // Resumed
+        let mut csv = Csv::continuing(open(&path, true).unwrap());
+        assert!(csv.observe(&record(3)));
+        let written = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            written,
+            CSV_HEADER.to_owned()
+                + "1,-1.5,-0.5,0.25,-0.75,1,0.5,2,3\n"
+                + "2,-1.5,-0.5,0.25,-0.75,1,0.5,2,3\n"
+                + "3,-1.5,-0.5,0.25,-0.75,1,0.5,2,3\n"
+        );
+    }
+
+    #[test]
+    fn json_lines_resume_appends_and_restart_truncates() {
+        let path = temp_path("telemetry.jsonl");
+        let line = |generation: usize| {
+            format!(
+                "{{\"generation\":{},\"mean_reward\":-1.5,\"elite_mean_reward\":-0.5,\"best_reward\":0.25,\"current_params_reward\":-0.75,\"noise_std_mean\":1.0,\"noise_std_min\":0.5,\"noise_std_max\":2.0,\"wall_time\":3.0}}\n",
+                generation
+            )
+        };
+        let mut json_lines = JsonLines::new(open(&path, false).unwrap());
+        assert!(json_lines.observe(&record(1)));
+        assert!(json_lines.observe(&record(2)));
+        assert_eq!(fs::read_to_string(&path).unwrap(), line(1) + &line(2));
+        let mut json_lines = JsonLines::new(open(&path, true).unwrap());
+        assert!(json_lines.observe(&record(3)));
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            line(1) + &line(2) + &line(3)
+        );
+        let mut json_lines = JsonLines::new(open(&path, false).unwrap());
+        assert!(json_lines.observe(&record(1)));
+        let written = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(written, line(1));
+    }
+}
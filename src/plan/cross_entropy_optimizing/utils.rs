@@ -1,3 +1,4 @@
+use super::encoding::Encoder;
 use crate::act::one_holding_switchable_nr_couple::OneHoldingSwitchableNRCouple;
 use crate::game::goal_couple_plugin::GoalCouple;
 use crate::plan::gradient_descent::*;
@@ -9,26 +10,7 @@ use ndarray::prelude::*;
 pub struct GoalQsCouple(pub Array1<f32>, pub Array1<f32>);
 
 pub fn encode(agent: &OneHoldingSwitchableNRCouple, non_holding_goal: &Vec2) -> (Array1<f32>, f32) {
-    let (_, holding_origin, holding_ls, _, _, _) = agent.holding().get_current_state();
-    let (_, _, non_holding_ls, _, _, _) = agent.non_holding().get_current_state();
-    let relative_goal = non_holding_goal.clone() - holding_origin.clone();
-
-    // Scaling
-    let scale = holding_ls.sum() + non_holding_ls.sum();
-    let scaled_holding_ls = holding_ls / scale;
-    let scaled_non_holding_ls = non_holding_ls / scale;
-    let scaled_relative_goal = relative_goal / scale;
-
-    let mut encoding = Array1::zeros(scaled_holding_ls.len() + scaled_non_holding_ls.len() + 2);
-    for (i, &l) in scaled_holding_ls.iter().enumerate() {
-        encoding[i] = l;
-    }
-    for (i, &l) in scaled_non_holding_ls.iter().enumerate() {
-        encoding[scaled_holding_ls.len() + i] = l;
-    }
-    encoding[scaled_holding_ls.len() + scaled_non_holding_ls.len()] = scaled_relative_goal[0];
-    encoding[scaled_holding_ls.len() + scaled_non_holding_ls.len() + 1] = scaled_relative_goal[1];
-    (encoding, scale)
+    Encoder::default().encode(agent, non_holding_goal, &[])
 }
 
 pub fn decode(forward_pass: &Array1<f32>, scale: f32, holding_origin: Vec2) -> Vec2 {
@@ -39,6 +21,7 @@ pub fn random_sample_solve(
     agent: &OneHoldingSwitchableNRCouple,
     goal_couple: &GoalCouple,
     goal_qs_couple: &mut GoalQsCouple,
+    seed: u64,
 ) {
     let (_, origin_holding, ls, qs, q_clamps, pivoting_side) = agent.holding().get_current_state();
     let loss_fn = |end: &Vec2, com: &Vec2, goal: &Vec2, origin: &Vec2| {
@@ -48,25 +31,38 @@ pub fn random_sample_solve(
     };
     let (_min_loss, best_q) = no_prior_random_sample_optimizer(
         10_000,
+        derive_seed(seed, 0),
         origin_holding,
         ls,
+        agent.holding().get_links(),
         qs[0],
         pivoting_side,
         q_clamps,
         &goal_couple.0,
+        None,
+        None,
         loss_fn,
     );
     goal_qs_couple.0 = best_q;
-    let (origin_non_holding, _) = get_end_verticex_and_com(origin_holding, ls, &goal_qs_couple.0);
+    let (origin_non_holding, _) = get_end_verticex_and_com(
+        origin_holding,
+        ls,
+        agent.holding().get_links(),
+        &goal_qs_couple.0,
+    );
     let (_, _, ls, qs, q_clamps, pivoting_side) = agent.non_holding().get_current_state();
     let (_min_loss, best_q) = no_prior_random_sample_optimizer(
         10_000,
+        derive_seed(seed, 1),
         &origin_non_holding,
         ls,
+        agent.non_holding().get_links(),
         qs[0],
         pivoting_side,
         q_clamps,
         &goal_couple.1,
+        None,
+        None,
         loss_fn,
     );
     goal_qs_couple.1 = best_q;
@@ -85,6 +81,7 @@ pub fn control(
             gradient_descent(
                 origin,
                 ls,
+                agent.holding().get_links(),
                 qs,
                 &goal_couple.0,
                 EndControl::JacobianTranspose,
@@ -106,6 +103,7 @@ pub fn control(
             gradient_descent(
                 origin,
                 ls,
+                agent.non_holding().get_links(),
                 qs,
                 &goal_couple.1,
                 EndControl::JacobianTranspose,
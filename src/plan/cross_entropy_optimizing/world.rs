@@ -1,4 +1,4 @@
-use crate::act::switchable_nr::Side;
+use crate::act::switchable_nr::{JointLimits, Side};
 use bevy::prelude::*;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
@@ -10,14 +10,23 @@ pub struct World {
     pub origin: Vec2,
     pub holding_ls: Vec<f32>,
     pub holding_q_clamps: Vec<(Option<f32>, Option<f32>)>,
+    /// (mass, com offset) per link, length weighted if none.
+    #[serde(default)]
+    pub holding_links: Option<Vec<(f32, f32)>>,
+    /// `JointLimits::default_for` the links if none.
+    #[serde(default)]
+    pub holding_joint_limits: Option<JointLimits>,
     pub non_holding_ls: Vec<f32>,
     pub non_holding_q_clamps: Vec<(Option<f32>, Option<f32>)>,
+    #[serde(default)]
+    pub non_holding_links: Option<Vec<(f32, f32)>>,
+    #[serde(default)]
+    pub non_holding_joint_limits: Option<JointLimits>,
     pub unscaled_relative_goal_region: (Vec2, Vec2),
 }
 
 impl World {
-    fn sample_qs(q_clamps: &[(Option<f32>, Option<f32>)]) -> Vec<f32> {
-        let mut rng = rand::thread_rng();
+    fn sample_qs<R: Rng>(q_clamps: &[(Option<f32>, Option<f32>)], rng: &mut R) -> Vec<f32> {
         q_clamps
             .iter()
             .map(|(min, max)| {
@@ -30,12 +39,12 @@ impl World {
             .collect()
     }
 
-    pub fn sample_holding_qs(&self) -> Vec<f32> {
-        World::sample_qs(&self.holding_q_clamps)
+    pub fn sample_holding_qs<R: Rng>(&self, rng: &mut R) -> Vec<f32> {
+        World::sample_qs(&self.holding_q_clamps, rng)
     }
 
-    pub fn sample_non_holding_qs(&self) -> Vec<f32> {
-        World::sample_qs(&self.non_holding_q_clamps)
+    pub fn sample_non_holding_qs<R: Rng>(&self, rng: &mut R) -> Vec<f32> {
+        World::sample_qs(&self.non_holding_q_clamps, rng)
     }
 
     fn get_q_clamps(q_clamps: &[(Option<f32>, Option<f32>)]) -> Vec<(f32, f32)> {
@@ -59,15 +68,12 @@ impl World {
         World::get_q_clamps(&self.non_holding_q_clamps)
     }
 
-    pub fn sample_goal(&self) -> Vec2 {
+    pub fn sample_goal<R: Rng>(&self, rng: &mut R) -> Vec2 {
         let (min, max) = self.unscaled_relative_goal_region;
         let scale = self.holding_ls.iter().sum::<f32>() + self.non_holding_ls.iter().sum::<f32>();
         let (min, max) = (min * scale, max * scale);
         let diff = max - min;
-        let rand_diff = Vec2::new(
-            rand::random::<f32>() * diff[0],
-            rand::random::<f32>() * diff[1],
-        );
+        let rand_diff = Vec2::new(rng.gen::<f32>() * diff[0], rng.gen::<f32>() * diff[1]);
         self.origin + min + rand_diff
     }
 }
@@ -0,0 +1,95 @@
+use super::*;
+use bevy::prelude::*;
+use ndarray::prelude::*;
+
+/// Cyclic Coordinate Descent.
+/// Each sweep rotates joints from the free end to the pivot, one at a time, so as to point the end
+/// towards the goal, clamping every joint as it goes.
+/// Returns delta qs that take the end to the goal.
+pub fn cyclic_coordinate_descent(
+    origin: &Vec2,
+    ls: &Array1<f32>,
+    qs: &Array1<f32>,
+    q_clamps: &Array1<(f32, f32)>,
+    goal: &Vec2,
+    max_iterations: usize,
+    tolerance: f32,
+) -> Array1<f32> {
+    let n = ls.len();
+    let mut new_qs = qs.clone();
+    for _ in 0..max_iterations {
+        if (get_all_vertices(origin, ls, &new_qs)[n] - *goal).length() < tolerance {
+            break;
+        }
+        for i in (0..n).rev() {
+            let vertices = get_all_vertices(origin, ls, &new_qs);
+            let to_end = vertices[n] - vertices[i];
+            let to_goal = *goal - vertices[i];
+            if to_end.length() == 0.0 || to_goal.length() == 0.0 {
+                continue;
+            }
+            let (min, max) = q_clamps[i];
+            new_qs[i] = (new_qs[i] + to_end.angle_between(to_goal))
+                .max(min)
+                .min(max);
+        }
+    }
+    new_qs - qs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// (origin, ls, qs, q clamps)
+    type Chain = (Vec2, Array1<f32>, Array1<f32>, Array1<(f32, f32)>);
+
+    fn chain() -> Chain {
+        (
+            Vec2::new(0.0, -0.1),
+            arr1(&[0.2, 0.3, 0.1]),
+            arr1(&[0.3, -0.3, -0.2]),
+            arr1(&[(-3.0, 3.0), (-2.5, 0.0), (-2.5, 0.0)]),
+        )
+    }
+
+    #[test]
+    fn reaches_reachable_goal_within_clamps() {
+        let (origin, ls, qs, q_clamps) = chain();
+        for target_qs in &[arr1(&[0.9, -1.2, -0.6]), arr1(&[-0.5, -0.4, -1.5])] {
+            let goal = get_all_vertices(&origin, &ls, target_qs)[3];
+            let delta_qs =
+                cyclic_coordinate_descent(&origin, &ls, &qs, &q_clamps, &goal, 100, 1e-3);
+            let new_qs = &qs + &delta_qs;
+            for (q, (min, max)) in new_qs.iter().zip(&q_clamps) {
+                assert!(min <= q && q <= max, "{} outside [{}, {}]", q, min, max);
+            }
+            let end = get_all_vertices(&origin, &ls, &new_qs)[3];
+            assert!((end - goal).length() < 1e-3, "{} vs {}", end, goal);
+        }
+    }
+
+    #[test]
+    fn never_leaves_clamps_for_unreachable_goal() {
+        let (origin, ls, qs, q_clamps) = chain();
+        for goal in &[
+            Vec2::new(2.0, 0.5),
+            Vec2::new(0.0, -0.1),
+            Vec2::new(0.05, 0.0),
+        ] {
+            let new_qs =
+                &qs + &cyclic_coordinate_descent(&origin, &ls, &qs, &q_clamps, goal, 100, 1e-3);
+            for (q, (min, max)) in new_qs.iter().zip(&q_clamps) {
+                assert!(min <= q && q <= max, "{} outside [{}, {}]", q, min, max);
+            }
+        }
+    }
+
+    #[test]
+    fn stays_put_at_goal() {
+        let (origin, ls, qs, q_clamps) = chain();
+        let goal = get_all_vertices(&origin, &ls, &qs)[3];
+        let delta_qs = cyclic_coordinate_descent(&origin, &ls, &qs, &q_clamps, &goal, 100, 1e-3);
+        assert!(delta_qs.iter().all(|&delta_q| delta_q == 0.0));
+    }
+}
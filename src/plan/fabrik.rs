@@ -0,0 +1,120 @@
+use super::*;
+use bevy::prelude::*;
+use ndarray::prelude::*;
+
+fn wrap_to_pi(angle: f32) -> f32 {
+    let pi = std::f32::consts::PI;
+    angle - 2.0 * pi * ((angle + pi) / (2.0 * pi)).floor()
+}
+
+/// Recovers joint angles from vertex positions, picking the representation of each q closest to
+/// the given qs and clamping it.
+fn vertices_to_qs(
+    vertices: &[Vec2],
+    qs: &Array1<f32>,
+    q_clamps: &Array1<(f32, f32)>,
+) -> Array1<f32> {
+    let n = qs.len();
+    let mut new_qs = Array1::zeros(n);
+    let mut cumulative_rotation = 0f32;
+    for i in 0..n {
+        let edge = vertices[i + 1] - vertices[i];
+        let absolute_rotation = edge[1].atan2(edge[0]);
+        let q = qs[i] + wrap_to_pi(absolute_rotation - cumulative_rotation - qs[i]);
+        let (min, max) = q_clamps[i];
+        new_qs[i] = q.max(min).min(max);
+        cumulative_rotation += new_qs[i];
+    }
+    new_qs
+}
+
+/// Forward And Backward Reaching Inverse Kinematics.
+/// Joint clamps are enforced after every pair of passes by snapping to the closest clamped pose.
+/// Returns delta qs that take the end to the goal.
+pub fn fabrik(
+    origin: &Vec2,
+    ls: &Array1<f32>,
+    qs: &Array1<f32>,
+    q_clamps: &Array1<(f32, f32)>,
+    goal: &Vec2,
+    max_iterations: usize,
+    tolerance: f32,
+) -> Array1<f32> {
+    let n = ls.len();
+    let mut new_qs = qs.clone();
+    for _ in 0..max_iterations {
+        let mut vertices = get_all_vertices(origin, ls, &new_qs);
+        if (vertices[n] - *goal).length() < tolerance {
+            break;
+        }
+        // Backward pass: pin the end to the goal
+        vertices[n] = *goal;
+        for i in (0..n).rev() {
+            let direction = (vertices[i] - vertices[i + 1]).normalize_or_zero();
+            vertices[i] = vertices[i + 1] + direction * ls[i];
+        }
+        // Forward pass: pin the first vertex back to the origin
+        vertices[0] = *origin;
+        for i in 0..n {
+            let direction = (vertices[i + 1] - vertices[i]).normalize_or_zero();
+            vertices[i + 1] = vertices[i] + direction * ls[i];
+        }
+        new_qs = vertices_to_qs(&vertices, &new_qs, q_clamps);
+    }
+    new_qs - qs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// (origin, ls, qs, q clamps)
+    type Chain = (Vec2, Array1<f32>, Array1<f32>, Array1<(f32, f32)>);
+
+    fn chain() -> Chain {
+        (
+            Vec2::new(0.0, -0.1),
+            arr1(&[0.2, 0.3, 0.1]),
+            arr1(&[0.3, -0.3, -0.2]),
+            arr1(&[(-3.0, 3.0), (-2.5, 0.0), (-2.5, 0.0)]),
+        )
+    }
+
+    #[test]
+    fn reaches_reachable_goal_within_clamps() {
+        let (origin, ls, qs, q_clamps) = chain();
+        for target_qs in &[arr1(&[0.9, -1.2, -0.6]), arr1(&[-0.5, -0.4, -1.5])] {
+            let goal = get_all_vertices(&origin, &ls, target_qs)[3];
+            let delta_qs = fabrik(&origin, &ls, &qs, &q_clamps, &goal, 100, 1e-3);
+            let new_qs = &qs + &delta_qs;
+            for (q, (min, max)) in new_qs.iter().zip(&q_clamps) {
+                assert!(min <= q && q <= max, "{} outside [{}, {}]", q, min, max);
+            }
+            let end = get_all_vertices(&origin, &ls, &new_qs)[3];
+            assert!((end - goal).length() < 1e-3, "{} vs {}", end, goal);
+        }
+    }
+
+    #[test]
+    fn never_leaves_clamps_for_unreachable_goal() {
+        let (origin, ls, qs, q_clamps) = chain();
+        for goal in &[
+            Vec2::new(2.0, 0.5),
+            Vec2::new(0.0, -0.1),
+            Vec2::new(0.05, 0.0),
+        ] {
+            let new_qs = &qs + &fabrik(&origin, &ls, &qs, &q_clamps, goal, 100, 1e-3);
+            for (q, (min, max)) in new_qs.iter().zip(&q_clamps) {
+                assert!(min <= q && q <= max, "{} outside [{}, {}]", q, min, max);
+            }
+        }
+    }
+
+    #[test]
+    fn stays_put_at_goal() {
+        let (origin, ls, qs, q_clamps) = chain();
+        let goal = get_all_vertices(&origin, &ls, &qs)[3];
+        let delta_qs = fabrik(&origin, &ls, &qs, &q_clamps, &goal, 100, 1e-3);
+        assert!(delta_qs.iter().all(|&delta_q| delta_q == 0.0));
+    }
+}
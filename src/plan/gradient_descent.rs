@@ -2,114 +2,362 @@ use super::*;
 use bevy::prelude::*;
 use ndarray::prelude::*;
 
+#[derive(Clone, Copy)]
 pub enum EndControl {
     JacobianTranspose,
     PseudoInverse,
+    /// Levenberg-Marquardt with the given damping factor.
+    DampedLeastSquares(f32),
+    /// Damped least squares end control, with the center of mass pushes projected into the null
+    /// space of the end jacobian so that they never move the end.
+    NullSpaceProjection(f32),
 }
 
+#[derive(Clone, Copy)]
 pub enum COMXGoalType {
     Pivot,
     PivotGoalMidpoint,
 }
 
-fn jacobian_transpose(a_i_0: &Vec<Vec2>, goal: &Vec2) -> Array1<f32> {
+fn jacobian(a_i_0: &[Vec2]) -> Array2<f32> {
     let n = a_i_0.len() - 1;
     // Free end coordinates
     let a_e_0 = *a_i_0.last().unwrap();
-    // Building jacobian
     let mut jacobian = Array2::zeros((2, n));
     for i in 0..n {
         let a_ie_0 = a_e_0 - a_i_0[i];
         jacobian[(0, i)] = -a_ie_0[1];
         jacobian[(1, i)] = a_ie_0[0];
     }
-    // Building delta_x
-    let delta_x = arr1(&[goal[0], goal[1]]) - arr1(&[a_e_0[0], a_e_0[1]]);
-    // Jacobian transpose
-    let delta_q = jacobian.t().dot(&delta_x);
-    delta_q
+    jacobian
 }
 
-fn com_x_sqr_gradient_with_qs(vertices: &Vec<Vec2>, com_x: f32) -> Array1<f32> {
-    // len(vertices) = n + 1
-    // Calculate y_1 + y_2 + y_3 + ... y_(n-1) + (y_n / 2); y_0 = 0 anyway so include it for cleaner code
-    let sum_y_i = vertices.iter().map(|vertex| vertex[1]).sum::<f32>();
-    let last_y_i = vertices.last().unwrap()[1];
-    let second_term = sum_y_i - (last_y_i / 2.0);
-    // Calculate negative gradient of x_c ^ 2 w.r.t. q_i
-    let n = vertices.len() - 1;
-    let nf = n as f32;
-    let mut delta_q_prev = (2.0 * com_x / nf) * second_term;
-    let mut delta_q = Vec::with_capacity(n);
-    delta_q.push(delta_q_prev);
-    for i in 1..n {
-        // Actual value
-        let delta_q_curr =
-            delta_q_prev - (2.0 * com_x / nf) * vertices[i][1] * (nf - (i as f32) + 0.5);
-        // Discounted responsibility for sending com_x to origin
-        let delta_q_curr = delta_q_curr / (i as f32);
-        delta_q_prev = delta_q_curr;
-        delta_q.push(delta_q_prev);
+fn delta_x(a_i_0: &[Vec2], goal: &Vec2) -> Array1<f32> {
+    let a_e_0 = *a_i_0.last().unwrap();
+    arr1(&[goal[0] - a_e_0[0], goal[1] - a_e_0[1]])
+}
+
+/// Moore-Penrose pseudo-inverse of a symmetric 2x2 matrix, via its eigen decomposition.
+fn symmetric_2x2_pseudo_inverse(m: &Array2<f32>) -> Array2<f32> {
+    let (a, b, c) = (m[(0, 0)], m[(0, 1)], m[(1, 1)]);
+    let mean = (a + c) / 2.0;
+    let radius = (((a - c) / 2.0).powi(2) + b * b).sqrt();
+    let eigen_values = [mean + radius, mean - radius];
+    let tolerance = eigen_values[0].abs() * 1e-6;
+    let mut pseudo_inverse = Array2::zeros((2, 2));
+    for (i, &eigen_value) in eigen_values.iter().enumerate() {
+        if eigen_value <= tolerance {
+            continue;
+        }
+        let eigen_vector = if b.abs() > f32::EPSILON * (a.abs() + c.abs()) {
+            Vec2::new(eigen_value - c, b).normalize()
+        } else if (a >= c) == (i == 0) {
+            Vec2::new(1.0, 0.0)
+        } else {
+            Vec2::new(0.0, 1.0)
+        };
+        for r in 0..2 {
+            for s in 0..2 {
+                pseudo_inverse[(r, s)] += eigen_vector[r] * eigen_vector[s] / eigen_value;
+            }
+        }
     }
-    // delta_q corresponds to negative gradient, so take a negative
-    let delta_q = -arr1(&delta_q);
-    delta_q
+    pseudo_inverse
+}
+
+/// J+ = Jt (J Jt)+, which holds for any rank of J.
+fn pseudo_inverse(jacobian: &Array2<f32>) -> Array2<f32> {
+    let j_jt = jacobian.dot(&jacobian.t());
+    jacobian.t().dot(&symmetric_2x2_pseudo_inverse(&j_jt))
+}
+
+/// I - J+ J; maps any delta_q to one that leaves the end (to first order) unchanged.
+fn null_space_projector(a_i_0: &[Vec2]) -> Array2<f32> {
+    let jacobian = jacobian(a_i_0);
+    let n = jacobian.ncols();
+    Array2::eye(n) - pseudo_inverse(&jacobian).dot(&jacobian)
+}
+
+fn jacobian_transpose(a_i_0: &[Vec2], goal: &Vec2) -> Array1<f32> {
+    jacobian(a_i_0).t().dot(&delta_x(a_i_0, goal))
+}
+
+fn jacobian_pseudo_inverse(a_i_0: &[Vec2], goal: &Vec2) -> Array1<f32> {
+    pseudo_inverse(&jacobian(a_i_0)).dot(&delta_x(a_i_0, goal))
 }
 
-fn com_y_gradient_with_qs(vertices: &Vec<Vec2>) -> Array1<f32> {
-    // len(vertices) = n + 1
-    // Calculate x_1 + x_2 + x_3 + ... x_(n-1) + (x_n / 2); x_0 = 0 anyway so include it for cleaner code
-    let sum_x_i = vertices.iter().map(|vertex| vertex[0]).sum::<f32>();
-    let last_x_i = vertices.last().unwrap()[0];
-    let second_term = sum_x_i - (last_x_i / 2.0);
-    // Calculate negative gradient of y_c w.r.t. q_i
-    let n = vertices.len() - 1;
-    let nf = n as f32;
-    let mut delta_q_prev = (1.0 / nf) * second_term;
-    let mut delta_q = Vec::with_capacity(n);
-    delta_q.push(delta_q_prev);
-    for i in 1..n {
-        // Actual value
-        let delta_q_curr = delta_q_prev - (1.0 / nf) * vertices[i][0] * (nf - (i as f32) + 0.5);
-        let delta_q_curr = delta_q_curr / (i as f32);
-        delta_q_prev = delta_q_curr;
-        delta_q.push(delta_q_prev);
+fn damped_least_squares(a_i_0: &[Vec2], goal: &Vec2, damping: f32) -> Array1<f32> {
+    let jacobian = jacobian(a_i_0);
+    // Jt (J Jt + lambda^2 I)^-1 delta_x; J Jt + lambda^2 I is invertible for any non zero lambda
+    let mut j_jt = jacobian.dot(&jacobian.t());
+    j_jt[(0, 0)] += damping * damping;
+    j_jt[(1, 1)] += damping * damping;
+    jacobian
+        .t()
+        .dot(&symmetric_2x2_pseudo_inverse(&j_jt))
+        .dot(&delta_x(a_i_0, goal))
+}
+
+/// Exact jacobian of the mass weighted center of mass w.r.t. qs, a 2 x n matrix.
+/// Joint j rigidly rotates links j..n, so column j is perp(sum_(i >= j) m_i (c_i - v_j)) / M,
+/// where c_i is the com of link i and v_j is vertex j.
+fn com_jacobian(vertices: &[Vec2], ls: &Array1<f32>, links: &Array1<(f32, f32)>) -> Array2<f32> {
+    let n = ls.len();
+    let total_mass = links.iter().map(|(mass, _)| mass).sum::<f32>();
+    let mut jacobian = Array2::zeros((2, n));
+    // Suffix sums of m_i * c_i and m_i
+    let mut weighted_coms = Vec2::ZERO;
+    let mut mass = 0.0;
+    for j in (0..n).rev() {
+        let (link_mass, offset) = links[j];
+        weighted_coms += link_mass * vertices[j].lerp(vertices[j + 1], offset / ls[j]);
+        mass += link_mass;
+        let moment = (weighted_coms - mass * vertices[j]) / total_mass;
+        jacobian[(0, j)] = -moment[1];
+        jacobian[(1, j)] = moment[0];
     }
-    // delta_q corresponds to positive gradient, so directly return
-    arr1(&delta_q)
+    jacobian
+}
+
+fn com_x_sqr_gradient_with_qs(com_jacobian: &Array2<f32>, com_x: f32) -> Array1<f32> {
+    // d(x_c ^ 2)/dq = 2 * x_c * dx_c/dq
+    2.0 * com_x * &com_jacobian.row(0)
+}
+
+fn com_y_gradient_with_qs(com_jacobian: &Array2<f32>) -> Array1<f32> {
+    com_jacobian.row(1).to_owned()
 }
 
 pub fn gradient_descent(
     origin: &Vec2,
     ls: &Array1<f32>,
+    links: &Array1<(f32, f32)>,
     qs: &Array1<f32>,
     goal: &Vec2,
     end_control: EndControl,
     com_x_goal_type: COMXGoalType,
 ) -> (Array1<f32>, Array1<f32>, Array1<f32>) {
-    let (vertices, com) = get_all_vertices_and_com(origin, ls, qs);
+    let (vertices, com) = get_all_vertices_and_com(origin, ls, links, qs);
     let take_end_to_given_goal = match end_control {
-        EndControl::JacobianTranspose => jacobian_transpose(&vertices, &goal),
-        EndControl::PseudoInverse => Array1::<f32>::zeros(qs.len()),
+        EndControl::JacobianTranspose => jacobian_transpose(&vertices, goal),
+        EndControl::PseudoInverse => jacobian_pseudo_inverse(&vertices, goal),
+        EndControl::DampedLeastSquares(damping) | EndControl::NullSpaceProjection(damping) => {
+            damped_least_squares(&vertices, goal, damping)
+        }
+    };
+    let null_space_projector = match end_control {
+        EndControl::NullSpaceProjection(_) => Some(null_space_projector(&vertices)),
+        _ => None,
     };
-    // Shift origin to first vertex
-    let origin = vertices[0];
-    let vertices = vertices
-        .iter()
-        .map(|&vertex| vertex - origin)
-        .collect::<Vec<Vec2>>();
+    let com_jacobian = com_jacobian(&vertices, ls, links);
     // Set com_x goal
     let com_x = com[0];
     let com_x_goal = match com_x_goal_type {
         COMXGoalType::Pivot => origin[0],
         COMXGoalType::PivotGoalMidpoint => (origin[0] + goal[0]) / 2.0,
     };
-    let push_com_x_from_its_goal = com_x_sqr_gradient_with_qs(&vertices, com_x - com_x_goal);
+    let push_com_x_from_its_goal = com_x_sqr_gradient_with_qs(&com_jacobian, com_x - com_x_goal);
     // Make com_y go downward
-    let push_com_y_upward = com_y_gradient_with_qs(&vertices);
+    let push_com_y_upward = com_y_gradient_with_qs(&com_jacobian);
+    // Secondary objectives only act in the null space of the end control
+    let (push_com_x_from_its_goal, push_com_y_upward) = match null_space_projector {
+        Some(projector) => (
+            projector.dot(&push_com_x_from_its_goal),
+            projector.dot(&push_com_y_upward),
+        ),
+        None => (push_com_x_from_its_goal, push_com_y_upward),
+    };
     (
         take_end_to_given_goal,
         push_com_x_from_its_goal,
         push_com_y_upward,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::act::switchable_nr::length_weighted_links;
+
+    fn assert_com_jacobian_matches_finite_differences(ls: &[f32], qs: &[f32]) {
+        let links = length_weighted_links(ls);
+        assert_weighted_com_jacobian_matches_finite_differences(ls, &links, qs);
+    }
+
+    fn assert_weighted_com_jacobian_matches_finite_differences(
+        ls: &[f32],
+        links: &[(f32, f32)],
+        qs: &[f32],
+    ) {
+        let origin = Vec2::new(3.0, -2.0);
+        let (ls, links, qs) = (arr1(ls), arr1(links), arr1(qs));
+        let (vertices, _) = get_all_vertices_and_com(&origin, &ls, &links, &qs);
+        let analytic = com_jacobian(&vertices, &ls, &links);
+        let h = 1e-3;
+        for j in 0..qs.len() {
+            let (mut qs_plus, mut qs_minus) = (qs.clone(), qs.clone());
+            qs_plus[j] += h;
+            qs_minus[j] -= h;
+            let (_, com_plus) = get_all_vertices_and_com(&origin, &ls, &links, &qs_plus);
+            let (_, com_minus) = get_all_vertices_and_com(&origin, &ls, &links, &qs_minus);
+            let numeric = (com_plus - com_minus) / (2.0 * h);
+            for k in 0..2 {
+                let tolerance = 1e-2 * numeric[k].abs().max(ls.sum() / 10.0);
+                assert!(
+                    (analytic[(k, j)] - numeric[k]).abs() < tolerance,
+                    "d com[{}]/d q[{}]: analytic {} vs numeric {}",
+                    k,
+                    j,
+                    analytic[(k, j)],
+                    numeric[k]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn com_jacobian_equal_lengths() {
+        assert_com_jacobian_matches_finite_differences(&[0.2, 0.2], &[0.3, -1.2]);
+        assert_com_jacobian_matches_finite_differences(&[64.; 4], &[0.1, 0.4, -0.7, 1.3]);
+    }
+
+    #[test]
+    fn com_jacobian_heterogeneous_lengths() {
+        assert_com_jacobian_matches_finite_differences(
+            &[0.2, 64.0, 3.0, 0.5],
+            &[-2.0, 0.5, 1.0, -0.3],
+        );
+        assert_com_jacobian_matches_finite_differences(&[0.2, 0.3, 0.1], &[-0.1, -0.1, -0.1]);
+    }
+
+    #[test]
+    fn com_jacobian_heterogeneous_masses() {
+        // Heavy torso, light forearm
+        assert_weighted_com_jacobian_matches_finite_differences(
+            &[0.3, 0.25, 0.25],
+            &[(40.0, 0.2), (3.0, 0.1), (1.5, 0.12)],
+            &[0.4, -1.0, -0.3],
+        );
+        assert_weighted_com_jacobian_matches_finite_differences(
+            &[0.2, 0.2],
+            &[(1.0, 0.0), (5.0, 0.2)],
+            &[0.3, -1.2],
+        );
+    }
+
+    #[test]
+    fn com_gradients_match_finite_differences() {
+        let origin = Vec2::new(0.0, -0.1);
+        let (ls, qs) = (arr1(&[0.2, 0.3, 0.1]), arr1(&[0.4, -1.0, -0.3]));
+        let links = arr1(&length_weighted_links(&[0.2, 0.3, 0.1]));
+        let goal = Vec2::new(-0.5, 0.0);
+        let (_, push_com_x, push_com_y) = gradient_descent(
+            &origin,
+            &ls,
+            &links,
+            &qs,
+            &goal,
+            EndControl::JacobianTranspose,
+            COMXGoalType::PivotGoalMidpoint,
+        );
+        let com_x_goal = (origin[0] + goal[0]) / 2.0;
+        let h = 1e-3;
+        for j in 0..qs.len() {
+            let (mut qs_plus, mut qs_minus) = (qs.clone(), qs.clone());
+            qs_plus[j] += h;
+            qs_minus[j] -= h;
+            let (_, com_plus) = get_all_vertices_and_com(&origin, &ls, &links, &qs_plus);
+            let (_, com_minus) = get_all_vertices_and_com(&origin, &ls, &links, &qs_minus);
+            let numeric_x = ((com_plus[0] - com_x_goal).powi(2)
+                - (com_minus[0] - com_x_goal).powi(2))
+                / (2.0 * h);
+            let numeric_y = (com_plus[1] - com_minus[1]) / (2.0 * h);
+            assert!((push_com_x[j] - numeric_x).abs() < 1e-3);
+            assert!((push_com_y[j] - numeric_y).abs() < 1e-3);
+        }
+    }
+
+    fn stretched_vertices() -> Vec<Vec2> {
+        // Every link points the same way, so the end jacobian has rank 1
+        let (ls, qs) = (arr1(&[0.2, 0.3, 0.1]), arr1(&[0.3, 0.0, 0.0]));
+        let links = arr1(&length_weighted_links(&[0.2, 0.3, 0.1]));
+        get_all_vertices_and_com(&Vec2::new(0.0, -0.1), &ls, &links, &qs).0
+    }
+
+    fn assert_close(a: &Array2<f32>, b: &Array2<f32>, tolerance: f32) {
+        assert_eq!(a.dim(), b.dim());
+        for (x, y) in a.iter().zip(b) {
+            assert!((x - y).abs() < tolerance, "{} vs {}\n{}\n{}", x, y, a, b);
+        }
+    }
+
+    #[test]
+    fn pseudo_inverse_is_a_generalised_inverse_at_singular_pose() {
+        let jacobian = jacobian(&stretched_vertices());
+        let pseudo_inverse = pseudo_inverse(&jacobian);
+        assert_close(
+            &jacobian.dot(&pseudo_inverse).dot(&jacobian),
+            &jacobian,
+            1e-5,
+        );
+        assert_close(
+            &pseudo_inverse.dot(&jacobian).dot(&pseudo_inverse),
+            &pseudo_inverse,
+            1e-3,
+        );
+    }
+
+    #[test]
+    fn damped_least_squares_stays_bounded_at_singular_pose() {
+        let vertices = stretched_vertices();
+        let end = *vertices.last().unwrap();
+        let along = (end - vertices[0]).normalize();
+        let across = Vec2::new(-along[1], along[0]);
+        for &damping in &[0.01, 0.1, 1.0] {
+            for &goal in &[end + along, end + across * 0.01, end - across] {
+                let delta_q = damped_least_squares(&vertices, &goal, damping);
+                // |delta_q| <= max_s s / (s^2 + lambda^2) |delta_x| = |delta_x| / (2 lambda)
+                let bound = (goal - end).length() / (2.0 * damping);
+                let norm = delta_q.dot(&delta_q).sqrt();
+                assert!(norm.is_finite() && norm <= bound * 1.001);
+            }
+        }
+    }
+
+    #[test]
+    fn null_space_projection_leaves_end_velocity_unchanged() {
+        let origin = Vec2::new(0.0, -0.1);
+        let ls = arr1(&[0.2, 0.3, 0.1]);
+        let links = arr1(&length_weighted_links(&[0.2, 0.3, 0.1]));
+        let goal = Vec2::new(-0.2, 0.3);
+        for qs in &[arr1(&[0.4, -1.0, -0.3]), arr1(&[0.3, 0.0, 0.0])] {
+            let (vertices, _) = get_all_vertices_and_com(&origin, &ls, &links, qs);
+            let jacobian = jacobian(&vertices);
+            let (take_end, push_com_x, push_com_y) = gradient_descent(
+                &origin,
+                &ls,
+                &links,
+                qs,
+                &goal,
+                EndControl::NullSpaceProjection(0.1),
+                COMXGoalType::Pivot,
+            );
+            let end_velocity = jacobian.dot(&take_end);
+            let combined = jacobian.dot(&(&take_end + &push_com_x - &push_com_y));
+            for k in 0..2 {
+                assert!((combined[k] - end_velocity[k]).abs() < 1e-5);
+            }
+            // The unprojected com pushes do move the end
+            let (_, raw_com_x, _) = gradient_descent(
+                &origin,
+                &ls,
+                &links,
+                qs,
+                &goal,
+                EndControl::DampedLeastSquares(0.1),
+                COMXGoalType::Pivot,
+            );
+            assert!(jacobian.dot(&raw_com_x).iter().any(|v| v.abs() > 1e-4));
+        }
+    }
+}
@@ -0,0 +1,466 @@
+use super::cross_entropy_optimizing::experiment::Experiment;
+use super::cross_entropy_optimizing::fcn::FCN;
+use super::cross_entropy_optimizing::policy::Policy;
+use super::cross_entropy_optimizing::utils::{random_sample_solve, GoalQsCouple};
+use super::cyclic_coordinate_descent::cyclic_coordinate_descent;
+use super::derive_seed;
+use super::fabrik::fabrik;
+use super::gradient_descent::*;
+use super::random_sampling::*;
+use crate::act::collision::SelfCollision;
+use crate::act::one_holding_switchable_nr_couple::OneHoldingSwitchableNRCouple;
+use crate::act::switchable_nr::{Side, SwitchableNR};
+use crate::game::goal_couple_plugin::GoalCouple;
+use bevy::prelude::*;
+use ndarray::prelude::*;
+
+pub type LossFn = Box<dyn Fn(&Vec2, &Vec2, &Vec2, &Vec2) -> f32 + Send + Sync>;
+
+pub enum IkSolution {
+    DeltaQs(Array1<f32>),
+    TargetQs(Array1<f32>),
+}
+
+impl IkSolution {
+    pub fn into_delta_qs(self, qs: &Array1<f32>) -> Array1<f32> {
+        match self {
+            IkSolution::DeltaQs(delta_qs) => delta_qs,
+            IkSolution::TargetQs(target_qs) => target_qs - qs,
+        }
+    }
+}
+
+/// Given a chain state and a goal for its free end, produces delta qs or target qs.
+/// Solvers may cache work across calls, e.g. a global solve that is only redone when the goal moves.
+pub trait IkSolver: Send + Sync {
+    fn solve(&mut self, agent: &SwitchableNR, goal: &Vec2) -> IkSolution;
+}
+
+/// The goal, origin and pivoting side a global solve was done for; the best qs only hold while
+/// all three stay the same.
+type SolveKey = (Vec2, Vec2, Side);
+
+fn solve_key(agent: &SwitchableNR, goal: &Vec2) -> SolveKey {
+    let (_, origin, _, _, _, pivoting_side) = agent.get_current_state();
+    (*goal, *origin, pivoting_side.clone())
+}
+
+pub struct GradientDescent {
+    pub end_control: EndControl,
+    pub com_x_goal_type: COMXGoalType,
+    pub com_x_weight: f32,
+    pub com_y_weight: f32,
+    pub self_collision: Option<SelfCollision>,
+    /// Rescales the end control to this length, for the end to move at a steady pace whatever its
+    /// distance to the goal.
+    pub step_size: Option<f32>,
+}
+
+impl IkSolver for GradientDescent {
+    fn solve(&mut self, agent: &SwitchableNR, goal: &Vec2) -> IkSolution {
+        let (_, origin, ls, qs, _, _) = agent.get_current_state();
+        let (take_end_to_given_goal, push_com_x_from_its_goal, push_com_y_upward) =
+            gradient_descent(
+                origin,
+                ls,
+                agent.get_links(),
+                qs,
+                goal,
+                self.end_control,
+                self.com_x_goal_type,
+            );
+        let take_end_to_given_goal = match self.step_size {
+            Some(step_size) => {
+                let length = take_end_to_given_goal.mapv(|e| e * e).sum().sqrt();
+                if length > 0.0 {
+                    step_size / length * take_end_to_given_goal
+                } else {
+                    take_end_to_given_goal
+                }
+            }
+            None => take_end_to_given_goal,
+        };
+        let push_links_into_each_other = match &self.self_collision {
+            Some(self_collision) => self_collision.gradient(&agent.get_all_vertices()),
+            None => Array1::zeros(qs.len()),
+        };
+        IkSolution::DeltaQs(
+            take_end_to_given_goal
+                - self.com_x_weight * push_com_x_from_its_goal
+                - self.com_y_weight * push_com_y_upward
+                - push_links_into_each_other,
+        )
+    }
+}
+
+pub struct NoPriorRandomSampling {
+    num_samples: usize,
+    seed: u64,
+    loss_fn: LossFn,
+    num_solves: u64,
+    solved: Option<(SolveKey, Array1<f32>)>,
+}
+
+impl NoPriorRandomSampling {
+    pub fn new(num_samples: usize, seed: u64, loss_fn: LossFn) -> NoPriorRandomSampling {
+        NoPriorRandomSampling {
+            num_samples,
+            seed,
+            loss_fn,
+            num_solves: 0,
+            solved: None,
+        }
+    }
+}
+
+impl IkSolver for NoPriorRandomSampling {
+    fn solve(&mut self, agent: &SwitchableNR, goal: &Vec2) -> IkSolution {
+        let key = solve_key(agent, goal);
+        match &self.solved {
+            Some((solved_key, best_qs)) if *solved_key == key => {
+                IkSolution::TargetQs(best_qs.clone())
+            }
+            _ => {
+                let (_, origin, ls, qs, q_clamps, pivoting_side) = agent.get_current_state();
+                let (_min_loss, best_qs) = no_prior_random_sample_optimizer(
+                    self.num_samples,
+                    derive_seed(self.seed, self.num_solves),
+                    origin,
+                    ls,
+                    agent.get_links(),
+                    qs[0],
+                    pivoting_side,
+                    q_clamps,
+                    goal,
+                    None,
+                    None,
+                    &self.loss_fn,
+                );
+                self.num_solves += 1;
+                self.solved = Some((key, best_qs.clone()));
+                IkSolution::TargetQs(best_qs)
+            }
+        }
+    }
+}
+
+pub struct FromCurrentStateRandomSampling {
+    num_samples: usize,
+    seed: u64,
+    q_mutation: f32,
+    loss_fn: LossFn,
+    num_solves: u64,
+    solved: Option<(SolveKey, Array1<f32>)>,
+}
+
+impl FromCurrentStateRandomSampling {
+    pub fn new(
+        num_samples: usize,
+        seed: u64,
+        q_mutation: f32,
+        loss_fn: LossFn,
+    ) -> FromCurrentStateRandomSampling {
+        FromCurrentStateRandomSampling {
+            num_samples,
+            seed,
+            q_mutation,
+            loss_fn,
+            num_solves: 0,
+            solved: None,
+        }
+    }
+}
+
+impl IkSolver for FromCurrentStateRandomSampling {
+    fn solve(&mut self, agent: &SwitchableNR, goal: &Vec2) -> IkSolution {
+        let key = solve_key(agent, goal);
+        match &self.solved {
+            Some((solved_key, best_qs)) if *solved_key == key => {
+                IkSolution::TargetQs(best_qs.clone())
+            }
+            _ => {
+                let (n, origin, ls, qs, q_clamps, pivoting_side) = agent.get_current_state();
+                let (_min_loss, best_qs) = from_current_state_random_sample_optimizer(
+                    self.num_samples,
+                    derive_seed(self.seed, self.num_solves),
+                    self.q_mutation,
+                    n,
+                    origin,
+                    ls,
+                    agent.get_links(),
+                    qs,
+                    pivoting_side,
+                    q_clamps,
+                    goal,
+                    None,
+                    None,
+                    &self.loss_fn,
+                );
+                self.num_solves += 1;
+                self.solved = Some((key, best_qs.clone()));
+                IkSolution::TargetQs(best_qs)
+            }
+        }
+    }
+}
+
+pub struct Fabrik {
+    pub max_iterations: usize,
+    pub tolerance: f32,
+}
+
+impl IkSolver for Fabrik {
+    fn solve(&mut self, agent: &SwitchableNR, goal: &Vec2) -> IkSolution {
+        let (_, origin, ls, qs, q_clamps, _) = agent.get_current_state();
+        IkSolution::DeltaQs(fabrik(
+            origin,
+            ls,
+            qs,
+            q_clamps,
+            goal,
+            self.max_iterations,
+            self.tolerance,
+        ))
+    }
+}
+
+pub struct CyclicCoordinateDescent {
+    pub max_iterations: usize,
+    pub tolerance: f32,
+}
+
+impl IkSolver for CyclicCoordinateDescent {
+    fn solve(&mut self, agent: &SwitchableNR, goal: &Vec2) -> IkSolution {
+        let (_, origin, ls, qs, q_clamps, _) = agent.get_current_state();
+        IkSolution::DeltaQs(cyclic_coordinate_descent(
+            origin,
+            ls,
+            qs,
+            q_clamps,
+            goal,
+            self.max_iterations,
+            self.tolerance,
+        ))
+    }
+}
+
+/// Heads for the target qs of a global solver at first, then more and more for the local delta qs:
+/// after t ticks on the same goal, origin and side, the global delta qs weigh 1 / (1 + t) ^ decay
+/// on top of the local ones.
+pub struct GlobalThenLocal {
+    global: Box<dyn IkSolver>,
+    local: Box<dyn IkSolver>,
+    decay: f32,
+    ticks: Option<(SolveKey, usize)>,
+}
+
+impl GlobalThenLocal {
+    pub fn new(global: Box<dyn IkSolver>, local: Box<dyn IkSolver>, decay: f32) -> GlobalThenLocal {
+        GlobalThenLocal {
+            global,
+            local,
+            decay,
+            ticks: None,
+        }
+    }
+}
+
+impl IkSolver for GlobalThenLocal {
+    fn solve(&mut self, agent: &SwitchableNR, goal: &Vec2) -> IkSolution {
+        let key = solve_key(agent, goal);
+        let ticks = match &self.ticks {
+            Some((ticks_key, ticks)) if *ticks_key == key => ticks + 1,
+            _ => 0,
+        };
+        self.ticks = Some((key, ticks));
+        let (_, _, _, qs, _, _) = agent.get_current_state();
+        let global_delta_qs = self.global.solve(agent, goal).into_delta_qs(qs);
+        let local_delta_qs = self.local.solve(agent, goal).into_delta_qs(qs);
+        let alpha = 1.0 / (1.0 + ticks as f32).powf(self.decay);
+        IkSolution::DeltaQs(alpha * global_delta_qs + local_delta_qs)
+    }
+}
+
+/// A trained experiment, for chains of as many links as its couple: the first links are taken as
+/// the holding chain and the rest as the non holding one. As in `reach_ohc_nn_ceo`, the policy
+/// places the joint between them and both chains are then globally solved. That joint is unclamped
+/// while solving, the non holding q0 being absolute.
+pub struct LearnedPolicy<P = FCN> {
+    experiment: Experiment<P>,
+    seed: u64,
+    num_solves: u64,
+    solved: Option<(SolveKey, Array1<f32>)>,
+}
+
+impl<P: Policy> LearnedPolicy<P> {
+    pub fn new(experiment: Experiment<P>, seed: u64) -> LearnedPolicy<P> {
+        LearnedPolicy {
+            experiment,
+            seed,
+            num_solves: 0,
+            solved: None,
+        }
+    }
+
+    fn couple(&self, agent: &SwitchableNR) -> OneHoldingSwitchableNRCouple {
+        let (n, origin, ls, qs, q_clamps, pivoting_side) = agent.get_current_state();
+        let world = &self.experiment.world;
+        let holding_n = world.holding_ls.len();
+        assert_eq!(
+            n,
+            holding_n + world.non_holding_ls.len(),
+            "Chain does not match the couple of the experiment."
+        );
+        let (ls, qs, q_clamps, links) = (
+            ls.to_vec(),
+            qs.to_vec(),
+            q_clamps.to_vec(),
+            agent.get_links().to_vec(),
+        );
+        let mut non_holding_qs = qs[holding_n..].to_vec();
+        non_holding_qs[0] += qs[..holding_n].iter().sum::<f32>();
+        let mut non_holding_q_clamps = q_clamps[holding_n..].to_vec();
+        non_holding_q_clamps[0] = (-f32::INFINITY, f32::INFINITY);
+        OneHoldingSwitchableNRCouple::new(
+            pivoting_side,
+            *origin,
+            &ls[..holding_n],
+            &qs[..holding_n],
+            &q_clamps[..holding_n],
+            Some(&links[..holding_n]),
+            None,
+            &ls[holding_n..],
+            &non_holding_qs,
+            &non_holding_q_clamps,
+            Some(&links[holding_n..]),
+            None,
+        )
+    }
+}
+
+impl<P: Policy + Send> IkSolver for LearnedPolicy<P> {
+    fn solve(&mut self, agent: &SwitchableNR, goal: &Vec2) -> IkSolution {
+        let key = solve_key(agent, goal);
+        if let Some((solved_key, best_qs)) = &self.solved {
+            if *solved_key == key {
+                return IkSolution::TargetQs(best_qs.clone());
+            }
+        }
+        let couple = self.couple(agent);
+        let Experiment {
+            policy,
+            encoder,
+            decoder,
+            ..
+        } = &self.experiment;
+        // Network pipeline
+        let (input, scale) = encoder.encode(&couple, goal, &[]);
+        let forward_pass = policy.step(&input, &mut policy.initial_state());
+        let holding_goal = decoder.decode(&forward_pass, scale, &couple);
+        let mut goal_qs_couple = GoalQsCouple(Array1::zeros(0), Array1::zeros(0));
+        random_sample_solve(
+            &couple,
+            &GoalCouple(holding_goal, *goal),
+            &mut goal_qs_couple,
+            derive_seed(self.seed, self.num_solves),
+        );
+        // Back to the qs of one chain, relative but for the first
+        let GoalQsCouple(holding_qs, mut non_holding_qs) = goal_qs_couple;
+        non_holding_qs[0] -= holding_qs.sum();
+        let best_qs = holding_qs
+            .iter()
+            .chain(non_holding_qs.iter())
+            .copied()
+            .collect::<Array1<f32>>();
+        self.num_solves += 1;
+        self.solved = Some((key, best_qs.clone()));
+        IkSolution::TargetQs(best_qs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::cross_entropy_optimizing::encoding::{Decoder, Encoder};
+    use crate::plan::cross_entropy_optimizing::fcn::{Activation, Initializer};
+    use crate::plan::cross_entropy_optimizing::world::World;
+    use crate::plan::seeded_rng;
+
+    fn learned_policy() -> LearnedPolicy {
+        let world = World {
+            holding_side: Side::Left,
+            origin: Vec2::ZERO,
+            holding_ls: vec![0.2, 0.2],
+            holding_q_clamps: vec![(None, None), (Some(-3.0), Some(0.0))],
+            holding_links: None,
+            holding_joint_limits: None,
+            non_holding_ls: vec![0.2, 0.2],
+            non_holding_q_clamps: vec![(None, None), (Some(-3.0), Some(0.0))],
+            non_holding_links: None,
+            non_holding_joint_limits: None,
+            unscaled_relative_goal_region: (Vec2::new(-0.8, -0.8), Vec2::new(0.1, 0.8)),
+        };
+        let encoder = Encoder::default();
+        let fcn = FCN::new(
+            vec![
+                (encoder.len(2, 2), Activation::Linear),
+                (2, Activation::Tanh),
+            ],
+            Initializer::XavierUniform,
+            &mut seeded_rng(0),
+        );
+        let experiment = Experiment {
+            policy: fcn,
+            ceo: None,
+            cma_es: None,
+            world,
+            encoder,
+            decoder: Decoder::RelativeGoal,
+        };
+        LearnedPolicy::new(experiment, 0)
+    }
+
+    fn chain() -> SwitchableNR {
+        let inf = f32::INFINITY;
+        SwitchableNR::new(
+            Vec2::new(0.0, -0.1),
+            &[0.2, 0.3, 0.1, 0.2],
+            &[0.4, -1.0, 0.5, -0.3],
+            &[(-inf, inf), (-3.0, 0.0), (-3.0, 3.0), (-3.0, 0.0)],
+            None,
+            None,
+            Side::Left,
+        )
+    }
+
+    #[test]
+    fn learned_policy_sees_the_chain_as_a_couple() {
+        let agent = chain();
+        let couple = learned_policy().couple(&agent);
+        for (a, b) in couple
+            .get_all_vertices()
+            .iter()
+            .zip(agent.get_all_vertices())
+        {
+            assert!((*a - b).length() < 1e-6);
+        }
+        assert!((couple.get_center_of_mass() - agent.get_center_of_mass()).length() < 1e-6);
+    }
+
+    #[test]
+    fn learned_policy_solves_once_per_goal() {
+        let (agent, goal) = (chain(), Vec2::new(-0.3, 0.2));
+        let mut solver = learned_policy();
+        let best_qs = match solver.solve(&agent, &goal) {
+            IkSolution::TargetQs(best_qs) => best_qs,
+            IkSolution::DeltaQs(_) => panic!("Expected target qs."),
+        };
+        assert_eq!(best_qs.len(), 4);
+        assert!(best_qs.iter().all(|q| q.is_finite()));
+        match solver.solve(&agent, &goal) {
+            IkSolution::TargetQs(again) => assert_eq!(again, best_qs),
+            IkSolution::DeltaQs(_) => panic!("Expected target qs."),
+        }
+        assert_eq!(solver.num_solves, 1);
+    }
+}
@@ -1,31 +1,56 @@
 pub mod cross_entropy_optimizing;
+pub mod cyclic_coordinate_descent;
+pub mod fabrik;
 pub mod gradient_descent;
+pub mod ik_solver;
 pub mod random_sampling;
 
 use bevy::prelude::*;
 use ndarray::prelude::*;
+use rand::{rngs::StdRng, SeedableRng};
 
-pub fn get_end_verticex_and_com(origin: &Vec2, ls: &Array1<f32>, qs: &Array1<f32>) -> (Vec2, Vec2) {
+/// Deterministically derives an independent seed for a sub stream (sample, candidate, episode...)
+/// of a seeded computation; splitmix64 finalizer over the mixed pair.
+pub fn derive_seed(seed: u64, stream: u64) -> u64 {
+    let mut z = seed
+        ^ stream
+            .wrapping_add(0x9e37_79b9_7f4a_7c15)
+            .wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+pub fn seeded_rng(seed: u64) -> StdRng {
+    StdRng::seed_from_u64(seed)
+}
+
+/// Links weigh in the com with their (mass, com offset along them), as in
+/// `SwitchableNR::get_center_of_mass`.
+pub fn get_end_verticex_and_com(
+    origin: &Vec2,
+    ls: &Array1<f32>,
+    links: &Array1<(f32, f32)>,
+    qs: &Array1<f32>,
+) -> (Vec2, Vec2) {
     let n = ls.len();
     let mut com = Vec2::ZERO;
+    let mut total_mass = 0.0;
     let mut e1 = origin.clone();
     let mut cumulative_rotation = 0f32;
     for i in 0..n {
         cumulative_rotation += qs[i];
         let e2 = e1 + Vec2::new(cumulative_rotation.cos(), cumulative_rotation.sin()) * ls[i];
-        com += ls[i] * (e1 + e2) / 2.0;
+        let (mass, offset) = links[i];
+        com += mass * e1.lerp(e2, offset / ls[i]);
+        total_mass += mass;
         e1 = e2;
     }
-    (e1, com / ls.sum())
+    (e1, com / total_mass)
 }
 
-pub fn get_all_vertices_and_com(
-    origin: &Vec2,
-    ls: &Array1<f32>,
-    qs: &Array1<f32>,
-) -> (Vec<Vec2>, Vec2) {
+pub fn get_all_vertices(origin: &Vec2, ls: &Array1<f32>, qs: &Array1<f32>) -> Vec<Vec2> {
     let n = ls.len();
-    let mut com = Vec2::ZERO;
     let mut vertices = Vec::with_capacity(ls.len() + 1);
     vertices.push(origin.clone());
     let mut e1 = origin.clone();
@@ -34,8 +59,23 @@ pub fn get_all_vertices_and_com(
         cumulative_rotation += qs[i];
         let e2 = e1 + Vec2::new(cumulative_rotation.cos(), cumulative_rotation.sin()) * ls[i];
         vertices.push(e2);
-        com += ls[i] * (e1 + e2) / 2.0;
         e1 = e2;
     }
-    (vertices, com / ls.sum())
+    vertices
+}
+
+pub fn get_all_vertices_and_com(
+    origin: &Vec2,
+    ls: &Array1<f32>,
+    links: &Array1<(f32, f32)>,
+    qs: &Array1<f32>,
+) -> (Vec<Vec2>, Vec2) {
+    let vertices = get_all_vertices(origin, ls, qs);
+    let mut com = Vec2::ZERO;
+    let mut total_mass = 0.0;
+    for (i, &(mass, offset)) in links.iter().enumerate() {
+        com += mass * vertices[i].lerp(vertices[i + 1], offset / ls[i]);
+        total_mass += mass;
+    }
+    (vertices, com / total_mass)
 }
@@ -1,4 +1,6 @@
 use super::*;
+use crate::act::collision::SelfCollision;
+use crate::act::dynamics::{gravity_torques, torque_margins};
 use crate::act::switchable_nr::Side;
 use bevy::prelude::*;
 use ndarray::prelude::*;
@@ -6,6 +8,7 @@ use ndarray_rand::rand_distr::Uniform;
 use ndarray_rand::RandomExt;
 use rand::prelude::*;
 use rayon::prelude::*;
+use std::cmp::Ordering;
 
 fn get_q0_clamp(q0: f32, pivoting_side: &Side) -> (f32, f32) {
     let pi = std::f32::consts::PI;
@@ -23,14 +26,96 @@ fn get_q0_clamp(q0: f32, pivoting_side: &Side) -> (f32, f32) {
     }
 }
 
+/// Per joint torque limits that the sampled poses should hold statically under gravity.
+pub struct TorqueLimits {
+    /// Largest torque magnitude per joint, the first at the origin.
+    pub limits: Array1<f32>,
+    pub gravity: f32,
+    /// (mass, com offset from the end vertex) of whatever hangs from the end, e.g. the non holding
+    /// chain of a couple, see `OneHoldingSwitchableNRCouple::holding_end_load`.
+    pub end_load: (f32, Vec2),
+    pub handling: TorqueLimitHandling,
+}
+
+#[derive(Clone, Copy)]
+pub enum TorqueLimitHandling {
+    /// Poses within the limits always win over those off them, which rank by their excess.
+    Constraint,
+    /// Adds the weighted excess to the loss.
+    Penalty(f32),
+}
+
+impl TorqueLimits {
+    /// Torque beyond the limits, summed over the joints; zero iff the pose can be held.
+    pub fn excess(
+        &self,
+        origin: &Vec2,
+        ls: &Array1<f32>,
+        links: &Array1<(f32, f32)>,
+        qs: &Array1<f32>,
+    ) -> f32 {
+        let vertices = get_all_vertices(origin, ls, qs);
+        let (end_mass, end_com_offset) = self.end_load;
+        let end_com = vertices[ls.len()] + end_com_offset;
+        let torques = gravity_torques(&vertices, ls, links, (end_mass, end_com), self.gravity);
+        torque_margins(&torques, &self.limits)
+            .iter()
+            .map(|margin| (-margin).max(0.0))
+            .sum()
+    }
+}
+
+/// (excess counted against the sample, loss), with the torque limits handled as asked.
+fn score<F>(
+    loss_fn: &F,
+    torque_limits: Option<&TorqueLimits>,
+    origin: &Vec2,
+    ls: &Array1<f32>,
+    links: &Array1<(f32, f32)>,
+    qs: &Array1<f32>,
+    goal: &Vec2,
+) -> (f32, f32)
+where
+    F: Fn(&Vec2, &Vec2, &Vec2, &Vec2) -> f32,
+{
+    let (end, com) = get_end_verticex_and_com(origin, ls, links, qs);
+    let loss = loss_fn(&end, &com, goal, origin);
+    match torque_limits {
+        None => (0.0, loss),
+        Some(torque_limits) => {
+            let excess = torque_limits.excess(origin, ls, links, qs);
+            match torque_limits.handling {
+                TorqueLimitHandling::Constraint => (excess, loss),
+                TorqueLimitHandling::Penalty(weight) => (0.0, loss + weight * excess),
+            }
+        }
+    }
+}
+
+/// Lowest excess then lowest loss wins; ties go to the lowest sample index so the result does not
+/// depend on how rayon splits the work.
+fn by_excess_then_loss_then_index(
+    x: &(f32, f32, usize, Array1<f32>),
+    y: &(f32, f32, usize, Array1<f32>),
+) -> Ordering {
+    x.0.partial_cmp(&y.0)
+        .unwrap()
+        .then(x.1.partial_cmp(&y.1).unwrap())
+        .then(x.2.cmp(&y.2))
+}
+
 pub fn no_prior_random_sample_optimizer<F>(
     num_samples: usize,
+    seed: u64,
     origin: &Vec2,
     ls: &Array1<f32>,
+    links: &Array1<(f32, f32)>,
     q0: f32,
     pivoting_side: &Side,
     q_clamps: &Array1<(f32, f32)>,
     goal: &Vec2,
+    torque_limits: Option<&TorqueLimits>,
+    self_collision: Option<&SelfCollision>,
     loss_fn: F,
 ) -> (f32, Array1<f32>)
 where
@@ -39,8 +124,8 @@ where
     let q0_clamp = get_q0_clamp(q0, pivoting_side);
     (0..num_samples)
         .into_par_iter()
-        .map(|_| {
-            let mut rng = thread_rng();
+        .map(|i| {
+            let mut rng = seeded_rng(derive_seed(seed, i as u64));
             let new_qs = q_clamps
                 .iter()
                 .enumerate()
@@ -52,23 +137,32 @@ where
                     }
                 })
                 .collect::<Array1<f32>>();
-            let (end, com) = get_end_verticex_and_com(origin, ls, &new_qs);
-            (loss_fn(&end, &com, goal, origin), new_qs)
+            let (excess, mut loss) =
+                score(&loss_fn, torque_limits, origin, ls, links, &new_qs, goal);
+            if let Some(self_collision) = self_collision {
+                loss += self_collision.penalty(&get_all_vertices(origin, ls, &new_qs));
+            }
+            (excess, loss, i, new_qs)
         })
-        .min_by(|x, y| x.0.partial_cmp(&y.0).unwrap())
+        .min_by(by_excess_then_loss_then_index)
+        .map(|(_, loss, _, new_qs)| (loss, new_qs))
         .unwrap()
 }
 
 pub fn from_current_state_random_sample_optimizer<F>(
     num_samples: usize,
+    seed: u64,
     q_mutation: f32,
     n: usize,
     origin: &Vec2,
     ls: &Array1<f32>,
+    links: &Array1<(f32, f32)>,
     qs: &Array1<f32>,
     pivoting_side: &Side,
     q_clamps: &Array1<(f32, f32)>,
     goal: &Vec2,
+    torque_limits: Option<&TorqueLimits>,
+    self_collision: Option<&SelfCollision>,
     loss_fn: F,
 ) -> (f32, Array1<f32>)
 where
@@ -77,8 +171,10 @@ where
     let (q0_min, q0_max) = get_q0_clamp(qs[0], pivoting_side);
     (0..num_samples)
         .into_par_iter()
-        .map(|_| {
-            let mutation = Array::random(qs.len(), Uniform::new(-q_mutation, q_mutation));
+        .map(|i| {
+            let mut rng = seeded_rng(derive_seed(seed, i as u64));
+            let mutation =
+                Array::random_using(qs.len(), Uniform::new(-q_mutation, q_mutation), &mut rng);
             let mut new_qs = qs + &mutation;
             if new_qs[0] < q0_min {
                 new_qs[0] = q0_min
@@ -93,9 +189,152 @@ where
                     new_qs[i] = max
                 }
             }
-            let (end, com) = get_end_verticex_and_com(origin, ls, &new_qs);
-            (loss_fn(&end, &com, goal, origin), new_qs)
+            let (excess, mut loss) =
+                score(&loss_fn, torque_limits, origin, ls, links, &new_qs, goal);
+            if let Some(self_collision) = self_collision {
+                loss += self_collision.penalty(&get_all_vertices(origin, ls, &new_qs));
+            }
+            (excess, loss, i, new_qs)
         })
-        .min_by(|x, y| x.0.partial_cmp(&y.0).unwrap())
+        .min_by(by_excess_then_loss_then_index)
+        .map(|(_, loss, _, new_qs)| (loss, new_qs))
         .unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::act::one_holding_switchable_nr_couple::OneHoldingSwitchableNRCouple;
+
+    fn loss_fn(end: &Vec2, _com: &Vec2, goal: &Vec2, _origin: &Vec2) -> f32 {
+        (*end - *goal).length()
+    }
+
+    /// Stretching out sideways to the goal takes far more torque at the origin than the limits
+    /// allow, hanging down takes none.
+    fn torque_limits(handling: TorqueLimitHandling) -> TorqueLimits {
+        TorqueLimits {
+            limits: arr1(&[5.0, 5.0]),
+            gravity: 10.0,
+            end_load: (0.5, Vec2::new(0.0, -0.5)),
+            handling,
+        }
+    }
+
+    /// (loss, qs) of each optimizer with these torque limits.
+    fn optimize(torque_limits: Option<&TorqueLimits>) -> Vec<(f32, Array1<f32>)> {
+        let pi = std::f32::consts::PI;
+        let origin = Vec2::ZERO;
+        let ls = arr1(&[1.0, 1.0]);
+        let links = arr1(&[(1.0, 0.5), (1.0, 0.5)]);
+        let q_clamps = arr1(&[(-pi, pi), (-pi, pi)]);
+        let hanging_qs = arr1(&[-pi / 2.0, 0.0]);
+        let goal = Vec2::new(2.0, 0.0);
+        vec![
+            no_prior_random_sample_optimizer(
+                2000,
+                0,
+                &origin,
+                &ls,
+                &links,
+                hanging_qs[0],
+                &Side::Left,
+                &q_clamps,
+                &goal,
+                torque_limits,
+                None,
+                loss_fn,
+            ),
+            from_current_state_random_sample_optimizer(
+                2000,
+                0,
+                3.0,
+                2,
+                &origin,
+                &ls,
+                &links,
+                &hanging_qs,
+                &Side::Left,
+                &q_clamps,
+                &goal,
+                torque_limits,
+                None,
+                loss_fn,
+            ),
+        ]
+    }
+
+    fn excess(qs: &Array1<f32>) -> f32 {
+        torque_limits(TorqueLimitHandling::Constraint).excess(
+            &Vec2::ZERO,
+            &arr1(&[1.0, 1.0]),
+            &arr1(&[(1.0, 0.5), (1.0, 0.5)]),
+            qs,
+        )
+    }
+
+    #[test]
+    fn infeasible_poses_lose_under_constraint() {
+        let constraint = torque_limits(TorqueLimitHandling::Constraint);
+        for ((free_loss, free_qs), (loss, qs)) in
+            optimize(None).into_iter().zip(optimize(Some(&constraint)))
+        {
+            assert!(excess(&free_qs) > 0.0);
+            assert_eq!(excess(&qs), 0.0);
+            assert!(loss > free_loss);
+        }
+    }
+
+    #[test]
+    fn infeasible_poses_are_penalised_under_penalty() {
+        let weight = 10.0;
+        let penalty = torque_limits(TorqueLimitHandling::Penalty(weight));
+        for ((_, free_qs), (loss, qs)) in optimize(None).into_iter().zip(optimize(Some(&penalty))) {
+            let (end, com) = get_end_verticex_and_com(
+                &Vec2::ZERO,
+                &arr1(&[1.0, 1.0]),
+                &arr1(&[(1.0, 0.5), (1.0, 0.5)]),
+                &qs,
+            );
+            let expected =
+                loss_fn(&end, &com, &Vec2::new(2.0, 0.0), &Vec2::ZERO) + weight * excess(&qs);
+            assert!((loss - expected).abs() < 1e-4);
+            assert!(excess(&qs) < excess(&free_qs));
+        }
+    }
+
+    #[test]
+    fn excess_agrees_with_the_couple() {
+        let (inf, pi) = (f32::INFINITY, std::f32::consts::PI);
+        let couple = OneHoldingSwitchableNRCouple::new(
+            &Side::Left,
+            Vec2::new(0.3, -0.2),
+            &[0.4, 0.3],
+            &[-0.4, 0.9],
+            &[(-inf, inf), (-pi, pi)],
+            Some(&[(2.0, 0.1), (1.0, 0.2)]),
+            None,
+            &[0.3, 0.4],
+            &[-1.2, 0.5],
+            &[(-inf, inf), (-pi, pi)],
+            Some(&[(1.5, 0.2), (0.5, 0.1)]),
+            None,
+        );
+        let gravity = 9.8;
+        let limits = arr1(&[3.0, 1.0]);
+        let margins = couple.holding_torque_margins(&limits, gravity);
+        let (_, origin, ls, qs, _, _) = couple.holding().get_current_state();
+        let torque_limits = TorqueLimits {
+            limits,
+            gravity,
+            end_load: couple.holding_end_load(),
+            handling: TorqueLimitHandling::Constraint,
+        };
+        let excess = margins.iter().map(|margin| (-margin).max(0.0)).sum::<f32>();
+        assert!(excess > 0.0);
+        assert!(
+            (torque_limits.excess(origin, ls, couple.holding().get_links(), qs) - excess).abs()
+                < 1e-4
+        );
+    }
+}